@@ -158,9 +158,13 @@ export interface Inhabitant {
     recovering: boolean;
     ready: boolean;
     starving: boolean;
+    underfed: boolean;
+    malnourished: boolean;
+    contagious: boolean;
     sleeping: boolean;
     tired: boolean;
     health: number;
+    morale: number;
 }
 
 export interface CraftingRecipe {
@@ -0,0 +1,8 @@
+CREATE TABLE "world_sectors" (
+  "id" serial PRIMARY KEY,
+  "world_id" int NOT NULL REFERENCES "worlds" ("id") ON DELETE CASCADE,
+  "x" int NOT NULL,
+  "y" int NOT NULL,
+  "hazard" int NOT NULL DEFAULT 0,
+  UNIQUE ("world_id", "x", "y")
+);
@@ -0,0 +1 @@
+ALTER TABLE "worlds" ADD COLUMN "data" jsonb NOT NULL DEFAULT '{}';
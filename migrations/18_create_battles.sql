@@ -0,0 +1,8 @@
+CREATE TABLE "battles" (
+  "id" serial PRIMARY KEY,
+  "bunker_id" int NOT NULL REFERENCES "bunkers" ("id") ON DELETE CASCADE,
+  "expedition_id" int NULL,
+  "created" timestamptz NOT NULL,
+  "log" jsonb NOT NULL
+);
+CREATE INDEX ON "battles" ("bunker_id", "expedition_id");
@@ -0,0 +1 @@
+ALTER TABLE "worlds" ADD COLUMN "pvp" boolean NOT NULL DEFAULT false;
@@ -0,0 +1,9 @@
+CREATE TABLE "recruits" (
+  "id" serial PRIMARY KEY,
+  "bunker_id" int NOT NULL REFERENCES "bunkers" ("id") ON DELETE CASCADE,
+  "name" varchar(100) NOT NULL,
+  "date_of_birth" date NOT NULL,
+  "created" timestamptz NOT NULL,
+  "data" jsonb NOT NULL
+);
+CREATE INDEX ON "recruits" ("bunker_id");
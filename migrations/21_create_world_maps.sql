@@ -0,0 +1,5 @@
+CREATE TABLE "world_maps" (
+  "world_id" int PRIMARY KEY REFERENCES "worlds" ("id") ON DELETE CASCADE,
+  "params" jsonb NOT NULL,
+  "image" bytea NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE "bunker_sectors" ADD COLUMN "last_visited" timestamptz NULL;
@@ -0,0 +1,26 @@
+UPDATE "bunkers" SET "data" = jsonb_set("data", '{power}', "data"->'power' || jsonb_build_object(
+    'priorities', COALESCE((
+      SELECT jsonb_agg(CASE "name"
+          WHEN 'waterTreatment' THEN 'water-treatment'
+          WHEN 'airRecycling' THEN 'air-recycling'
+          ELSE "name" END ORDER BY "position")
+        FROM jsonb_array_elements_text("data"->'power'->'priorities')
+          WITH ORDINALITY AS "p"("name", "position")
+    ), '[]'::jsonb),
+    'limits', COALESCE((
+      SELECT jsonb_object_agg(CASE "key"
+          WHEN 'waterTreatment' THEN 'water-treatment'
+          WHEN 'airRecycling' THEN 'air-recycling'
+          ELSE "key" END, "value")
+        FROM jsonb_each("data"->'power'->'limits')
+    ), '{}'::jsonb),
+    'supply', '{}'::jsonb
+  ))
+  WHERE jsonb_typeof("data"->'power') = 'object';
+
+UPDATE "bunkers" SET "data" = ("data" - 'waterTreatment' - 'airRecycling')
+  || jsonb_build_object('facilities', jsonb_build_object(
+    'reactor', COALESCE("data"->'reactor', '{}'::jsonb),
+    'water-treatment', COALESCE("data"->'waterTreatment', '{}'::jsonb),
+    'air-recycling', COALESCE("data"->'airRecycling', '{}'::jsonb)
+  ));
@@ -0,0 +1,9 @@
+UPDATE "bunkers" SET "data" = jsonb_set("data", '{cafeteria,nutrients}', jsonb_build_object(
+    'protein', COALESCE("data"->'cafeteria'->'food', '0'::jsonb),
+    'fat', COALESCE("data"->'cafeteria'->'food', '0'::jsonb),
+    'fiber', COALESCE("data"->'cafeteria'->'food', '0'::jsonb),
+    'vitamins', COALESCE("data"->'cafeteria'->'food', '0'::jsonb),
+    'minerals', COALESCE("data"->'cafeteria'->'food', '0'::jsonb)
+  ))
+  WHERE jsonb_typeof("data"->'cafeteria') = 'object'
+    AND NOT "data"->'cafeteria' ? 'nutrients';
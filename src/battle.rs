@@ -3,163 +3,852 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use rand::{seq::IteratorRandom, Rng};
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
 use tracing::debug;
 
 use crate::{
-    data::ITEM_TYPES,
-    db::inhabitants::{Inhabitant, SkillType},
+    data::{EnemyBehaviour, EnemyType, ENEMY_TYPES, ITEM_TYPES},
+    db::{
+        expeditions::Tactic,
+        inhabitants::{Inhabitant, Skill, SkillType},
+    },
     error,
-    util::skill_roll,
+    util::{roll_dice_with, skill_roll_with},
 };
 
+const MAX_ROUNDS: i32 = 40;
+const ESCAPE_RANGE: i32 = 50;
+
+/// Everything that happened in a battle. The seed, parameters and participants are enough to
+/// replay it.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BattleLog {
+    pub seed: u64,
+    #[serde(flatten)]
+    pub params: BattleParams,
+    #[serde(default)]
+    pub team: Vec<Participant>,
+    #[serde(default)]
+    pub defenders: Vec<Participant>, // only in pvp battles
+    pub enemy_type: String,
+    pub quantity: i32,
+    pub rounds: Vec<BattleRound>,
+    pub result: BattleResult,
+    pub loot: BTreeMap<String, i32>,
+}
+
+#[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BattleParams {
+    pub max_number: i32,
+    pub tactic: Tactic,
+    pub terrain: String,
+    #[serde(default)]
+    pub raid: bool, // raiders attacking the bunker, the defenders can't evade
+    #[serde(default)]
+    pub defense: i32, // fortifications protecting the defenders
+    #[serde(default)]
+    pub pvp: bool, // the opponents are inhabitants of another bunker
+}
+
+/// Combat stats of an inhabitant at the start of a battle.
+#[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Participant {
+    pub id: i32,
+    pub name: String,
+    pub skills: Vec<Skill>,
+    pub weapon_type: Option<String>,
+    pub ammo: i32,
+    pub armor_type: Option<String>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BattleRound {
+    pub range: i32,
+    pub events: Vec<BattleEvent>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BattleEvent {
+    #[serde(rename_all = "camelCase")]
+    Attack {
+        attacker: Combatant,
+        target: Combatant,
+        hit: bool,
+        damage: i32,
+        ammo_used: i32,
+    },
+    Killed {
+        attacker: Combatant,
+        target: Combatant,
+    },
+    Incapacitated {
+        target: Combatant,
+    },
+    OutOfAmmo {
+        target: Combatant,
+    },
+    Fled {
+        target: Combatant,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Combatant {
+    Inhabitant { id: i32, name: String },
+    Enemy { index: usize },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BattleResult {
+    Evaded,
+    Victory,
+    Retreated,
+    Escaped,
+}
+
+struct Enemy {
+    enemy_type: &'static EnemyType,
+    hp: i32,
+    fled: bool,
+}
+
+impl Enemy {
+    fn is_active(&self) -> bool {
+        self.hp > 0 && !self.fled
+    }
+}
+
+enum Turn {
+    Member(usize),
+    Enemy(usize),
+}
+
+impl BattleLog {
+    pub fn is_retreat(&self) -> bool {
+        self.result == BattleResult::Retreated
+    }
+
+    /// Describes the battle in the same terms as the mission reports.
+    pub fn to_report(&self) -> String {
+        let (name, name_plural) = match ENEMY_TYPES.get(&self.enemy_type) {
+            Some(enemy_type) => (
+                enemy_type.name.to_lowercase(),
+                enemy_type.name_plural.to_lowercase(),
+            ),
+            None if self.params.pvp => (format!("defender"), format!("defenders")),
+            None => (self.enemy_type.clone(), self.enemy_type.clone()),
+        };
+        let mut report = String::new();
+        if self.result == BattleResult::Evaded {
+            report.push_str(&format!("Successfully evaded a group of {}\n", name_plural));
+            return report;
+        }
+        if self.params.pvp {
+            if self.quantity == 1 {
+                report.push_str("Engaged a single defender\n");
+            } else {
+                report.push_str(&format!("Engaged {} defenders\n", self.quantity));
+            }
+        } else if self.params.raid {
+            if self.quantity == 1 {
+                report.push_str(&format!("The bunker was attacked by a single {}\n", name));
+            } else {
+                report.push_str(&format!(
+                    "The bunker was attacked by {} {}\n",
+                    self.quantity, name_plural
+                ));
+            }
+        } else if self.quantity == 1 {
+            report.push_str(&format!("Encountered a single {}\n", name));
+        } else {
+            report.push_str(&format!("Encountered {} {}\n", self.quantity, name_plural));
+        }
+        let mut killed = 0;
+        for event in self.rounds.iter().flat_map(|r| &r.events) {
+            match event {
+                BattleEvent::Attack { .. } => (),
+                BattleEvent::Killed { attacker, .. } => {
+                    killed += 1;
+                    report.push_str(&format!("{} killed a {}\n", get_name(attacker), name));
+                }
+                BattleEvent::Incapacitated { target } => {
+                    report.push_str(&format!("{} was incapacitated\n", get_name(target)));
+                }
+                BattleEvent::OutOfAmmo { target } => {
+                    report.push_str(&format!("{} ran out of ammunition\n", get_name(target)));
+                }
+                BattleEvent::Fled { .. } => {
+                    report.push_str(&format!("A wounded {} fled\n", name));
+                }
+            }
+        }
+        match self.result {
+            BattleResult::Victory if self.params.pvp => {
+                report.push_str("The defenders were overpowered\n")
+            }
+            BattleResult::Victory if killed > 1 => {
+                report.push_str(&format!("{} {} were killed\n", killed, name_plural));
+            }
+            BattleResult::Retreated if self.params.raid => {
+                report.push_str("The defenders were overwhelmed\n")
+            }
+            BattleResult::Retreated => report.push_str("Retreated\n"),
+            BattleResult::Escaped => report.push_str("Escaped\n"),
+            _ => (),
+        }
+        report
+    }
+}
+
+impl From<&Inhabitant> for Participant {
+    fn from(member: &Inhabitant) -> Participant {
+        Participant {
+            id: member.id,
+            name: member.name.clone(),
+            skills: member.data.skills.clone(),
+            weapon_type: member.data.weapon_type.clone(),
+            ammo: member.data.ammo,
+            armor_type: member.data.armor_type.clone(),
+        }
+    }
+}
+
+fn get_name(combatant: &Combatant) -> String {
+    match combatant {
+        Combatant::Inhabitant { name, .. } => name.clone(),
+        Combatant::Enemy { index } => format!("Enemy {}", index + 1),
+    }
+}
+
+fn get_combatant(member: &Inhabitant) -> Combatant {
+    Combatant::Inhabitant {
+        id: member.id,
+        name: member.name.clone(),
+    }
+}
+
+fn choose_enemy_type<R: Rng>(
+    rng: &mut R,
+    params: &BattleParams,
+) -> Result<&'static EnemyType, error::Error> {
+    let mut candidates: Vec<&'static EnemyType> = ENEMY_TYPES
+        .values()
+        .filter(|e| {
+            if params.raid {
+                e.raider
+            } else {
+                e.biomes.is_empty() || e.biomes.contains(&params.terrain)
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.id.cmp(&b.id));
+    candidates
+        .into_iter()
+        .choose(rng)
+        .ok_or_else(|| error::internal_error("No enemy types for terrain"))
+}
+
+fn get_armor(member: &Inhabitant) -> i32 {
+    member
+        .data
+        .armor_type
+        .as_ref()
+        .and_then(|armor_type| ITEM_TYPES.get(armor_type))
+        .map(|armor| armor.armor)
+        .unwrap_or(0)
+}
+
+fn get_hit_chance(weapon_range: i32, range: i32) -> f64 {
+    (weapon_range as f64 - range as f64 + 1.0) / (weapon_range as f64)
+}
+
 pub fn encounter(
     team: &mut Vec<Inhabitant>,
-    report_body: &mut String,
     max_number: i32,
-) -> Result<bool, error::Error> {
+    tactic: Tactic,
+    terrain: &str,
+) -> Result<BattleLog, error::Error> {
+    let params = BattleParams {
+        max_number,
+        tactic,
+        terrain: terrain.to_owned(),
+        raid: false,
+        defense: 0,
+        pvp: false,
+    };
+    simulate(team, params, rand::thread_rng().gen())
+}
+
+/// Resolves an attack on the bunker by the given number of raiders.
+pub fn raid(
+    defenders: &mut Vec<Inhabitant>,
+    quantity: i32,
+    terrain: &str,
+    defense: i32,
+) -> Result<BattleLog, error::Error> {
+    let params = BattleParams {
+        max_number: quantity,
+        tactic: Tactic::Defensive,
+        terrain: terrain.to_owned(),
+        raid: true,
+        defense,
+        pvp: false,
+    };
+    simulate(defenders, params, rand::thread_rng().gen())
+}
+
+/// Resolves an attack by a team of inhabitants on the defenders of another bunker.
+pub fn skirmish(
+    attackers: &mut Vec<Inhabitant>,
+    defenders: &mut Vec<Inhabitant>,
+    tactic: Tactic,
+    defense: i32,
+) -> Result<BattleLog, error::Error> {
+    let params = BattleParams {
+        max_number: defenders.len() as i32,
+        tactic,
+        terrain: String::new(),
+        raid: true,
+        defense,
+        pvp: true,
+    };
+    simulate_skirmish(attackers, defenders, params, rand::thread_rng().gen())
+}
+
+fn simulate_skirmish(
+    attackers: &mut Vec<Inhabitant>,
+    defenders: &mut Vec<Inhabitant>,
+    params: BattleParams,
+    seed: u64,
+) -> Result<BattleLog, error::Error> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let tactic = params.tactic;
+    let defense = params.defense;
+    let mut log = BattleLog {
+        seed,
+        params,
+        team: attackers.iter().map(Participant::from).collect(),
+        defenders: defenders.iter().map(Participant::from).collect(),
+        enemy_type: String::new(),
+        quantity: defenders.len() as i32,
+        rounds: vec![],
+        result: BattleResult::Victory,
+        loot: BTreeMap::new(),
+    };
+    if defenders.is_empty() {
+        return Ok(log);
+    }
+    for member in attackers.iter_mut().chain(defenders.iter_mut()) {
+        member.data.hp = 50;
+    }
+    log.result = BattleResult::Retreated;
+    let mut range: i32 = rng.gen_range(5..30);
+    let advance = if tactic == Tactic::Aggressive { 2 } else { 1 };
+    for _ in 0..MAX_ROUNDS {
+        let mut round = BattleRound {
+            range,
+            events: vec![],
+        };
+        let mut order: Vec<(i32, bool, usize)> = vec![];
+        for (i, member) in attackers.iter().enumerate() {
+            let initiative = member.get_skill_level(SkillType::Combat) + rng.gen_range(0..6);
+            order.push((initiative, true, i));
+        }
+        for (i, member) in defenders.iter().enumerate() {
+            let initiative = member.get_skill_level(SkillType::Combat) + rng.gen_range(0..6);
+            order.push((initiative, false, i));
+        }
+        order.sort_by_key(|(initiative, _, _)| -initiative);
+        for (_, attacking, i) in order {
+            if attacking {
+                inhabitant_attack(
+                    &mut rng,
+                    &mut attackers[i],
+                    defenders,
+                    range,
+                    defense,
+                    &mut round,
+                )?;
+            } else {
+                inhabitant_attack(&mut rng, &mut defenders[i], attackers, range, 0, &mut round)?;
+            }
+        }
+        log.rounds.push(round);
+        if !defenders.iter().any(|m| m.data.hp > 0) {
+            log.result = BattleResult::Victory;
+            break;
+        }
+        if !attackers.iter().any(|m| m.data.hp > 0) {
+            break;
+        }
+        range = (range - advance).max(0);
+    }
+    Ok(log)
+}
+
+fn simulate(
+    team: &mut Vec<Inhabitant>,
+    params: BattleParams,
+    seed: u64,
+) -> Result<BattleLog, error::Error> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let enemy_type = choose_enemy_type(&mut rng, &params)?;
+    let max_number = params.max_number;
+    let tactic = params.tactic;
+    let raid = params.raid;
+    let defense = params.defense;
+    let mut log = BattleLog {
+        seed,
+        params,
+        team: team.iter().map(Participant::from).collect(),
+        defenders: vec![],
+        enemy_type: enemy_type.id.clone(),
+        quantity: 0,
+        rounds: vec![],
+        result: BattleResult::Evaded,
+        loot: BTreeMap::new(),
+    };
     if max_number < 1 {
-        return Ok(true);
+        return Ok(log);
     }
     let stealth_sum: i32 = team
         .iter()
         .map(|i| i.get_skill_level(SkillType::Stealth))
         .sum();
     let stealth_avg = (stealth_sum as f64 / team.len() as f64).ceil() as i32;
-    if skill_roll(0.05, stealth_avg) {
-        report_body.push_str(&format!("Successfully evaded a band of marauders\n",));
+    let stealth_chance = if tactic == Tactic::Avoid { 0.1 } else { 0.05 };
+    if !raid
+        && tactic != Tactic::Aggressive
+        && skill_roll_with(&mut rng, stealth_chance, stealth_avg)
+    {
         for member in team {
             member.add_xp(SkillType::Stealth, 60);
         }
+        return Ok(log);
+    }
+    log.quantity = if raid || max_number == 1 {
+        max_number
     } else {
-        let quantity: i32 = if max_number == 1 {
-            1
-        } else {
-            rand::thread_rng().gen_range(1..max_number)
+        rng.gen_range(1..max_number)
+    };
+    let mut enemies: Vec<Enemy> = (0..log.quantity)
+        .map(|_| Enemy {
+            enemy_type,
+            hp: enemy_type.hp,
+            fled: false,
+        })
+        .collect();
+    let mut range: i32 = rng.gen_range(5..50);
+    for member in team.iter_mut() {
+        member.data.hp = 50;
+    }
+    log.result = BattleResult::Retreated;
+    for _ in 0..MAX_ROUNDS {
+        debug!("range: {}", range);
+        let mut round = BattleRound {
+            range,
+            events: vec![],
         };
-        if quantity == 1 {
-            report_body.push_str(&format!("Encountered a single marauder\n"));
-        } else {
-            report_body.push_str(&format!("Encountered {} marauders\n", quantity));
-        }
-        let enemy_hp = 50;
-        let mut enemies = vec![enemy_hp; quantity as usize];
-        let mut range: i32 = rand::thread_rng().gen_range(5..50);
-        for member in team.iter_mut() {
-            member.data.hp = 50;
-        }
-        debug!("enemies: {:?}", enemies);
-        for _ in 0..(range * 2).min(40) {
-            debug!("range: {}", range);
-            for member in team.iter_mut() {
-                if member.data.hp <= 0 {
-                    continue;
-                }
-                debug!("{} is ready", member.name);
-                let mut weapon_range = 1;
-                let mut weapon_damage = 1;
-                let skill = match &member.data.weapon_type {
-                    Some(item_type) => {
-                        let weapon = ITEM_TYPES
-                            .get(item_type)
-                            .ok_or_else(|| error::internal_error("Item type not found"))?;
-                        if weapon.melee_weapon {
-                            weapon_range = weapon.range;
-                            weapon_damage = weapon.damage;
-                            SkillType::MeleeWeapons
-                        } else if member.data.ammo > 0 {
-                            weapon_range = weapon.range;
-                            weapon_damage = weapon.damage;
-                            SkillType::Guns
-                        } else {
-                            SkillType::Unarmed
-                        }
-                    }
-                    None => SkillType::Unarmed,
-                };
-                let hit_chance = (weapon_range as f64 - range as f64 + 1.0) / (weapon_range as f64);
-                if hit_chance >= 0.1 {
-                    debug!("{} fires", member.name);
-                    if skill_roll(hit_chance, member.get_skill_level(skill)) {
-                        if let Some(enemy) = enemies
-                            .iter_mut()
-                            .filter(|e| **e > 0)
-                            .choose(&mut rand::thread_rng())
-                        {
-                            let damage = rand::thread_rng().gen_range(1..weapon_damage + 1);
-                            *enemy -= damage;
-                            debug!("hit: damage = {}, hp = {}", damage, *enemy);
-                            member.add_xp(skill, damage);
-                            if *enemy <= 0 {
-                                debug!("enemy is dead");
-                                report_body
-                                    .push_str(&format!("{} killed a marauder\n", member.name,));
-                            }
-                        }
-                    }
-                    if skill == SkillType::Guns {
-                        member.data.ammo -= 1;
-                        if member.data.ammo < 1 {
-                            report_body
-                                .push_str(&format!("{} ran out of ammunition\n", member.name));
-                        }
-                    }
+        let mut order: Vec<(i32, Turn)> = vec![];
+        for (i, member) in team.iter().enumerate() {
+            let initiative = member.get_skill_level(SkillType::Combat) + rng.gen_range(0..6);
+            order.push((initiative, Turn::Member(i)));
+        }
+        for (i, enemy) in enemies.iter().enumerate() {
+            let initiative = enemy.enemy_type.initiative + rng.gen_range(0..6);
+            order.push((initiative, Turn::Enemy(i)));
+        }
+        order.sort_by_key(|(initiative, _)| -initiative);
+        for (_, turn) in order {
+            match turn {
+                Turn::Member(i) => {
+                    member_attack(&mut rng, &mut team[i], &mut enemies, range, &mut round)?
                 }
+                Turn::Enemy(i) => enemy_attack(
+                    &mut rng,
+                    i,
+                    &enemies[i],
+                    team,
+                    range,
+                    tactic,
+                    defense,
+                    &mut round,
+                ),
             }
-            enemies.retain(|&e| e > 0);
-            debug!("enemies left: {:?}", enemies);
-            if enemies.is_empty() {
-                debug!("all enemies dead");
-                if quantity > 1 {
-                    report_body.push_str(&format!("All {} marauders were killed\n", quantity));
-                }
-                break;
+        }
+        for (i, enemy) in enemies.iter_mut().enumerate() {
+            if enemy.is_active()
+                && enemy.enemy_type.behaviour == EnemyBehaviour::Cowardly
+                && enemy.hp < enemy.enemy_type.hp / 2
+            {
+                enemy.fled = true;
+                round.events.push(BattleEvent::Fled {
+                    target: Combatant::Enemy { index: i },
+                });
             }
-            for (i, _) in enemies.iter().enumerate() {
-                // TODO: stats/abilities for marauders
-                debug!("enemey {} is ready", i);
-                let weapon_damage = 5;
-                let weapon_range = 15;
-                let hit_chance = (weapon_range as f64 - range as f64 + 1.0) / (weapon_range as f64);
-                if hit_chance >= 0.1 {
-                    debug!("enemey {} fires", i);
-                    if skill_roll(hit_chance, 0) {
-                        if let Some(member) = team
-                            .iter_mut()
-                            .filter(|m| m.data.hp > 0)
-                            .choose(&mut rand::thread_rng())
-                        {
-                            let damage = rand::thread_rng().gen_range(1..weapon_damage + 1);
-                            member.data.hp -= damage;
-                            debug!(
-                                "enemey {} hits {}: damage = {}, hp = {}",
-                                i, member.name, damage, member.data.hp
-                            );
-                            if member.data.hp < 0 {
-                                debug!("{} is incapacitated", member.name);
-                                member.data.bleeding = true;
-                                member.data.wounded = true;
-                                member.data.health -= rand::thread_rng().gen_range(1..50);
-                                report_body
-                                    .push_str(&format!("{} was incapacitated\n", member.name));
-                            }
-                        }
-                    }
-                }
+        }
+        log.rounds.push(round);
+        if !enemies.iter().any(|e| e.is_active()) {
+            debug!("no enemies left");
+            log.result = BattleResult::Victory;
+            break;
+        }
+        if !team.iter().any(|m| m.data.hp > 0) {
+            debug!("all incapacitated");
+            return Ok(log);
+        }
+        let team_move = match tactic {
+            Tactic::Aggressive => -1,
+            Tactic::Defensive => 0,
+            Tactic::Avoid => 1,
+        };
+        let enemy_move = if enemies.iter().any(|e| {
+            e.is_active()
+                && (e.enemy_type.behaviour == EnemyBehaviour::Aggressive
+                    || range > e.enemy_type.range)
+        }) {
+            -1
+        } else {
+            0
+        };
+        range = (range + team_move + enemy_move).max(0);
+        if range > ESCAPE_RANGE {
+            log.result = BattleResult::Escaped;
+            return Ok(log);
+        }
+    }
+    if log.result != BattleResult::Victory {
+        return Ok(log);
+    }
+    for enemy in enemies.iter().filter(|e| e.hp <= 0) {
+        let mut loot_table: Vec<_> = enemy.enemy_type.loot.iter().collect();
+        loot_table.sort_by(|a, b| a.0.cmp(b.0));
+        for (item_type_id, entry) in loot_table {
+            if roll_dice_with(&mut rng, entry.chance, 1) {
+                let quantity = rng.gen_range(entry.min..entry.max + 1);
+                *log.loot.entry(item_type_id.clone()).or_insert(0) += quantity;
             }
-            if !team.iter().any(|m| m.data.hp > 0) {
-                debug!("all incapacitated");
-                report_body.push_str(&format!("Retreated\n"));
-                return Ok(false);
+        }
+    }
+    Ok(log)
+}
+
+/// The skill used, the range and the damage of the inhabitant's weapon.
+fn get_weapon(member: &Inhabitant) -> Result<(SkillType, i32, i32), error::Error> {
+    if let Some(item_type) = &member.data.weapon_type {
+        let weapon = ITEM_TYPES
+            .get(item_type)
+            .ok_or_else(|| error::internal_error("Item type not found"))?;
+        if weapon.melee_weapon {
+            return Ok((SkillType::MeleeWeapons, weapon.range, weapon.damage));
+        } else if member.data.ammo > 0 {
+            return Ok((SkillType::Guns, weapon.range, weapon.damage));
+        }
+    }
+    Ok((SkillType::Unarmed, 1, 1))
+}
+
+fn inhabitant_attack<R: Rng>(
+    rng: &mut R,
+    member: &mut Inhabitant,
+    opponents: &mut [Inhabitant],
+    range: i32,
+    defense: i32,
+    round: &mut BattleRound,
+) -> Result<(), error::Error> {
+    if member.data.hp <= 0 {
+        return Ok(());
+    }
+    let (skill, weapon_range, weapon_damage) = get_weapon(member)?;
+    let hit_chance = get_hit_chance(weapon_range, range) * 100.0 / (100.0 + defense as f64);
+    if hit_chance < 0.1 {
+        return Ok(());
+    }
+    let opponent = match opponents.iter_mut().filter(|m| m.data.hp > 0).choose(rng) {
+        Some(opponent) => opponent,
+        None => return Ok(()),
+    };
+    member.add_xp(SkillType::Combat, 5);
+    let hit = skill_roll_with(rng, hit_chance, member.get_skill_level(skill));
+    let mut damage = 0;
+    if hit {
+        damage = (rng.gen_range(1..weapon_damage + 1) - get_armor(opponent)).max(0);
+        opponent.data.hp -= damage;
+        member.add_xp(skill, damage);
+    }
+    let ammo_used = if skill == SkillType::Guns { 1 } else { 0 };
+    member.data.ammo -= ammo_used;
+    round.events.push(BattleEvent::Attack {
+        attacker: get_combatant(member),
+        target: get_combatant(opponent),
+        hit,
+        damage,
+        ammo_used,
+    });
+    if hit && opponent.data.hp < 0 {
+        incapacitate(rng, opponent, round);
+    }
+    if ammo_used > 0 && member.data.ammo < 1 {
+        round.events.push(BattleEvent::OutOfAmmo {
+            target: get_combatant(member),
+        });
+    }
+    Ok(())
+}
+
+fn incapacitate<R: Rng>(rng: &mut R, member: &mut Inhabitant, round: &mut BattleRound) {
+    member.data.bleeding = true;
+    member.data.wounded = true;
+    member.data.health -= rng.gen_range(1..50);
+    round.events.push(BattleEvent::Incapacitated {
+        target: get_combatant(member),
+    });
+}
+
+fn member_attack<R: Rng>(
+    rng: &mut R,
+    member: &mut Inhabitant,
+    enemies: &mut [Enemy],
+    range: i32,
+    round: &mut BattleRound,
+) -> Result<(), error::Error> {
+    if member.data.hp <= 0 {
+        return Ok(());
+    }
+    let (skill, weapon_range, weapon_damage) = get_weapon(member)?;
+    let hit_chance = get_hit_chance(weapon_range, range);
+    if hit_chance < 0.1 {
+        return Ok(());
+    }
+    let (index, enemy) = match enemies
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, e)| e.is_active())
+        .choose(rng)
+    {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    member.add_xp(SkillType::Combat, 5);
+    let hit = skill_roll_with(rng, hit_chance, member.get_skill_level(skill));
+    let mut damage = 0;
+    if hit {
+        damage = (rng.gen_range(1..weapon_damage + 1) - enemy.enemy_type.armor).max(0);
+        enemy.hp -= damage;
+        member.add_xp(skill, damage);
+    }
+    let ammo_used = if skill == SkillType::Guns { 1 } else { 0 };
+    member.data.ammo -= ammo_used;
+    let attacker = get_combatant(member);
+    let target = Combatant::Enemy { index };
+    debug!(
+        "{} attacks enemy {}: damage = {}",
+        member.name, index, damage
+    );
+    round.events.push(BattleEvent::Attack {
+        attacker: attacker.clone(),
+        target: target.clone(),
+        hit,
+        damage,
+        ammo_used,
+    });
+    if hit && enemy.hp <= 0 {
+        round.events.push(BattleEvent::Killed {
+            attacker: attacker.clone(),
+            target,
+        });
+    }
+    if ammo_used > 0 && member.data.ammo < 1 {
+        round
+            .events
+            .push(BattleEvent::OutOfAmmo { target: attacker });
+    }
+    Ok(())
+}
+
+fn enemy_attack<R: Rng>(
+    rng: &mut R,
+    index: usize,
+    enemy: &Enemy,
+    team: &mut [Inhabitant],
+    range: i32,
+    tactic: Tactic,
+    defense: i32,
+    round: &mut BattleRound,
+) {
+    if !enemy.is_active() {
+        return;
+    }
+    let mut hit_chance = get_hit_chance(enemy.enemy_type.range, range);
+    if hit_chance < 0.1 {
+        return;
+    }
+    if tactic == Tactic::Defensive {
+        // Holding position means the team can make use of cover
+        hit_chance *= 0.75;
+    }
+    hit_chance *= 100.0 / (100.0 + defense as f64);
+    let member = match team.iter_mut().filter(|m| m.data.hp > 0).choose(rng) {
+        Some(member) => member,
+        None => return,
+    };
+    let hit = skill_roll_with(rng, hit_chance, enemy.enemy_type.accuracy);
+    let mut damage = 0;
+    if hit {
+        damage = (rng.gen_range(1..enemy.enemy_type.damage + 1) - get_armor(member)).max(0);
+        member.data.hp -= damage;
+    }
+    debug!(
+        "enemy {} attacks {}: damage = {}, hp = {}",
+        index, member.name, damage, member.data.hp
+    );
+    round.events.push(BattleEvent::Attack {
+        attacker: Combatant::Enemy { index },
+        target: get_combatant(member),
+        hit,
+        damage,
+        ammo_used: 0,
+    });
+    if hit && member.data.hp < 0 {
+        incapacitate(rng, member, round);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::TERRAIN_TYPES,
+        db::inhabitants::{test_inhabitant, InhabitantData},
+    };
+
+    fn create_team() -> Vec<Inhabitant> {
+        (1..=3)
+            .map(|id| {
+                test_inhabitant(
+                    id,
+                    InhabitantData {
+                        health: 100,
+                        weapon_type: Some("9mm-pistol".to_owned()),
+                        ammo: 10,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn to_inhabitant(participant: &Participant) -> Inhabitant {
+        let mut inhabitant = test_inhabitant(
+            participant.id,
+            InhabitantData {
+                health: 100,
+                skills: participant.skills.clone(),
+                weapon_type: participant.weapon_type.clone(),
+                ammo: participant.ammo,
+                armor_type: participant.armor_type.clone(),
+                ..Default::default()
+            },
+        );
+        inhabitant.name = participant.name.clone();
+        inhabitant
+    }
+
+    /// Runs the battle described by an existing log again with the same random numbers.
+    fn replay(log: &BattleLog) -> Result<BattleLog, error::Error> {
+        let mut team = log.team.iter().map(to_inhabitant).collect();
+        if log.params.pvp {
+            let mut defenders = log.defenders.iter().map(to_inhabitant).collect();
+            simulate_skirmish(&mut team, &mut defenders, log.params.clone(), log.seed)
+        } else {
+            simulate(&mut team, log.params.clone(), log.seed)
+        }
+    }
+
+    #[test]
+    fn every_passable_terrain_has_enemies() {
+        let mut rng = rand::thread_rng();
+        for terrain in TERRAIN_TYPES.values().filter(|t| !t.impassable) {
+            for raid in [false, true] {
+                let params = BattleParams {
+                    max_number: 1,
+                    tactic: Tactic::Defensive,
+                    terrain: terrain.id.clone(),
+                    raid,
+                    defense: 0,
+                    pvp: false,
+                };
+                let enemy_type = choose_enemy_type(&mut rng, &params).unwrap();
+                assert!(!raid || enemy_type.raider, "{}", terrain.id);
             }
-            if range > 0 {
-                range -= 1;
+        }
+    }
+
+    #[test]
+    fn skirmish_ends_with_one_side_standing() {
+        let log = skirmish(&mut create_team(), &mut vec![], Tactic::Aggressive, 0).unwrap();
+        assert_eq!(BattleResult::Victory, log.result);
+        let mut attackers = create_team();
+        let mut defenders = create_team();
+        let log = skirmish(&mut attackers, &mut defenders, Tactic::Defensive, 50).unwrap();
+        match log.result {
+            BattleResult::Victory => assert!(defenders.iter().all(|m| m.data.hp <= 0)),
+            BattleResult::Retreated => assert!(
+                log.rounds.len() == MAX_ROUNDS as usize || attackers.iter().all(|m| m.data.hp <= 0)
+            ),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn can_replay_battle() {
+        for seed in 0..20 {
+            let params = BattleParams {
+                max_number: 5,
+                tactic: Tactic::Aggressive,
+                terrain: "road".to_owned(),
+                raid: false,
+                defense: 0,
+                pvp: false,
+            };
+            let mut team = create_team();
+            let log = simulate(&mut team, params, seed).unwrap();
+            assert_ne!(BattleResult::Evaded, log.result);
+            assert!(!log.rounds.is_empty());
+            // Changing the team afterwards doesn't change the replay
+            for member in &mut team {
+                member.data.weapon_type = None;
+                member.add_xp(SkillType::Combat, 1000);
             }
+            let replayed = replay(&log).unwrap();
+            assert_eq!(log, replayed);
+            assert_eq!(log.to_report(), replayed.to_report());
         }
-        if !enemies.is_empty() {
-            report_body.push_str(&format!("Retreated\n"));
-            return Ok(false);
+    }
+
+    #[test]
+    fn can_replay_skirmish() {
+        for seed in 0..5 {
+            let params = BattleParams {
+                max_number: 3,
+                tactic: Tactic::Aggressive,
+                terrain: String::new(),
+                raid: true,
+                defense: 10,
+                pvp: true,
+            };
+            let log =
+                simulate_skirmish(&mut create_team(), &mut create_team(), params, seed).unwrap();
+            assert_eq!(log, replay(&log).unwrap());
         }
     }
-    Ok(true)
 }
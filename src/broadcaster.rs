@@ -20,11 +20,42 @@ pub enum Message {
     Tick,
     Message,
     Expedition,
+    ExpeditionEvent {
+        expedition_id: i32,
+        event: ExpeditionEvent,
+    },
     Broadcast {
         bunker: i32,
         name: String,
         message: String,
     },
+    WorldEvent {
+        event_type: String,
+        name: String,
+        text: String,
+    },
+    ReactorEvent {
+        event: ReactorEvent,
+    },
+    Malfunction {
+        facility: String,
+    },
+    Meltdown {
+        sector: String,
+    },
+}
+
+#[derive(Clone, Copy, serde::Serialize)]
+pub enum ReactorEvent {
+    Leak,
+    Meltdown,
+}
+
+#[derive(Clone, Copy, serde::Serialize)]
+pub enum ExpeditionEvent {
+    Halfway,
+    Encounter,
+    Wounded,
 }
 
 #[derive(actix::Message)]
@@ -3,20 +3,90 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
+use rand::Rng;
 use sqlx::PgPool;
-use tracing::warn;
 
 use crate::{
-    data::ITEM_TYPES,
+    data::{MealType, ITEM_TYPES, MEAL_TYPES},
     db::{
-        bunkers::{self, Bunker, Crop},
-        inhabitants::{Assignment, Inhabitant, SkillType},
+        bunkers::{self, Bunker, CafeteriaStatus},
+        inhabitants::{Assignment, Inhabitant, Nutrient, SkillType},
         items,
     },
     error,
-    util::{roll_dice, skill_roll},
 };
 
+/// Calories in a portion, enough to satisfy 12 hours of hunger.
+pub const PORTION_CALORIES: i32 = 500;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MealPlanRequest {
+    meals: Vec<String>,
+}
+
+impl CafeteriaStatus {
+    /// Adds freshly prepared portions, averaging their quality with the existing stock.
+    pub fn add_portions(&mut self, portions: i32, quality: i32, nutrients: &[Nutrient]) {
+        if portions < 1 {
+            return;
+        }
+        self.quality = (self.quality * self.food + quality * portions) / (self.food + portions);
+        self.food += portions;
+        for nutrient in nutrients {
+            *self.nutrients.entry(*nutrient).or_insert(0) += portions;
+        }
+    }
+
+    /// Takes a portion from the stock and returns the nutrients it contained.
+    pub fn take_portion<R: Rng>(&mut self, rng: &mut R) -> Vec<Nutrient> {
+        let mut nutrients = vec![];
+        if self.food < 1 {
+            return nutrients;
+        }
+        for (nutrient, portions) in self.nutrients.iter_mut() {
+            if *portions > 0 && rng.gen_range(0..self.food) < *portions {
+                *portions -= 1;
+                nutrients.push(*nutrient);
+            }
+        }
+        self.food -= 1;
+        nutrients
+    }
+
+    /// Removes portions without anyone eating them, e.g. when they spoil.
+    pub fn discard_portions(&mut self, portions: i32) {
+        let remaining = (self.food - portions).max(0);
+        for count in self.nutrients.values_mut() {
+            *count = *count * remaining / self.food.max(1);
+        }
+        self.food = remaining;
+    }
+}
+
+/// Meals the cooks may prepare in order of preference.
+fn get_meal_plan(cafeteria: &CafeteriaStatus) -> Vec<&'static MealType> {
+    if cafeteria.meal_plan.is_empty() {
+        let mut meal_types: Vec<&MealType> = MEAL_TYPES.values().collect();
+        meal_types.sort_by_key(|t| (-t.min_level, &t.id));
+        meal_types
+    } else {
+        cafeteria
+            .meal_plan
+            .iter()
+            .filter_map(|id| MEAL_TYPES.get(id))
+            .collect()
+    }
+}
+
+fn get_portions(calories: i32, cooking_level: i32) -> i32 {
+    // Skilled cooks make the ingredients go further
+    let stretch = 1.0 + 0.05 * cooking_level as f64;
+    (calories as f64 * stretch / PORTION_CALORIES as f64).round() as i32
+}
+
 pub async fn handle_tick(
     pool: &PgPool,
     bunker: &mut Bunker,
@@ -30,32 +100,118 @@ pub async fn handle_tick(
             i.is_ready() && !i.data.sleeping && i.data.assignment == Some(Assignment::Cafeteria)
         })
         .collect();
-    if !workers.is_empty() && bunker.data.cafeteria.food < num_inhabitants * 2 {
-        let ingredients = items::get_items(pool, bunker.id).await?;
-        let mut food_to_cook = num_inhabitants * 3;
-        let mut cooked = false;
-        for ingredient in ingredients {
-            if let Some(item_type) = ITEM_TYPES.get(&ingredient.item_type) {
-                if !item_type.food {
-                    continue;
-                }
-                let quantity = food_to_cook.min(ingredient.quantity);
-                if items::remove_item(pool, bunker.id, &item_type.id, quantity).await? {
-                    cooked = true;
-                    bunker.data.cafeteria.food += quantity;
-                    food_to_cook -= quantity;
-                    for worker in workers.iter_mut() {
-                        worker.add_xp(SkillType::Cooking, quantity);
-                    }
-                    if food_to_cook <= 0 {
-                        break;
-                    }
+    let cafeteria = &mut bunker.data.cafeteria;
+    if workers.is_empty() || cafeteria.food >= num_inhabitants * 2 {
+        return Ok(());
+    }
+    let cooking_level = workers
+        .iter()
+        .map(|w| w.get_skill_level(SkillType::Cooking))
+        .max()
+        .unwrap_or(0);
+    let mut stock: HashMap<String, i32> = items::get_items(pool, bunker.id)
+        .await?
+        .into_iter()
+        .map(|item| (item.item_type, item.quantity))
+        .collect();
+    let mut used: HashMap<String, i32> = HashMap::new();
+    let mut calories_to_cook = num_inhabitants * 3 * PORTION_CALORIES * power_level / 100;
+    for meal_type in get_meal_plan(cafeteria) {
+        if meal_type.min_level > cooking_level {
+            continue;
+        }
+        let quality = (50 + 10 * cooking_level).min(100);
+        while calories_to_cook > 0
+            && meal_type
+                .ingredients
+                .iter()
+                .all(|(id, quantity)| stock.get(id).copied().unwrap_or(0) >= *quantity)
+        {
+            let mut calories = 0;
+            let mut nutrients = vec![];
+            for (id, quantity) in &meal_type.ingredients {
+                *stock.entry(id.clone()).or_insert(0) -= quantity;
+                *used.entry(id.clone()).or_insert(0) += quantity;
+                if let Some(item_type) = ITEM_TYPES.get(id) {
+                    calories += item_type.calories * quantity;
+                    nutrients.extend(item_type.nutrients.iter().copied());
                 }
             }
+            nutrients.sort_by_key(|n| *n as i32);
+            nutrients.dedup();
+            cafeteria.add_portions(get_portions(calories, cooking_level), quality, &nutrients);
+            calories_to_cook -= calories;
         }
-        if cooked {
-            items::remove_empty_items(pool, bunker.id).await?;
+    }
+    // Cook whatever else is left on its own, using up the ingredients that spoil the fastest
+    let mut ingredients: Vec<_> = stock
+        .iter()
+        .filter(|(_, quantity)| **quantity > 0)
+        .filter_map(|(id, quantity)| ITEM_TYPES.get(id).map(|t| (t, *quantity)))
+        .filter(|(item_type, _)| item_type.food && item_type.calories > 0)
+        .collect();
+    ingredients.sort_by_key(|(t, _)| (t.shelf_life < 1, t.shelf_life, &t.id));
+    let quality = (20 + 5 * cooking_level).min(60);
+    for (item_type, quantity) in ingredients {
+        if calories_to_cook <= 0 {
+            break;
         }
+        let quantity =
+            quantity.min((calories_to_cook + item_type.calories - 1) / item_type.calories);
+        let calories = item_type.calories * quantity;
+        *used.entry(item_type.id.clone()).or_insert(0) += quantity;
+        cafeteria.add_portions(
+            get_portions(calories, cooking_level),
+            quality,
+            &item_type.nutrients,
+        );
+        calories_to_cook -= calories;
+    }
+    if used.is_empty() {
+        return Ok(());
     }
+    for (id, quantity) in &used {
+        items::remove_item(pool, bunker.id, id, *quantity).await?;
+        for worker in workers.iter_mut() {
+            worker.add_xp(SkillType::Cooking, *quantity);
+        }
+    }
+    items::remove_empty_items(pool, bunker.id).await?;
     Ok(())
 }
+
+pub async fn set_meal_plan(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &MealPlanRequest,
+) -> Result<(), error::Error> {
+    if request.meals.iter().any(|id| !MEAL_TYPES.contains_key(id)) {
+        Err(error::client_error("INVALID_MEAL_TYPE"))?;
+    }
+    bunker.data.cafeteria.meal_plan = request.meals.clone();
+    bunkers::update_bunker_data_query(bunker)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn portions_keep_track_of_nutrients() {
+        let mut cafeteria = CafeteriaStatus::default();
+        cafeteria.add_portions(10, 80, &[Nutrient::Protein, Nutrient::Fiber]);
+        cafeteria.add_portions(10, 40, &[Nutrient::Fiber]);
+        assert_eq!(20, cafeteria.food);
+        assert_eq!(60, cafeteria.quality);
+        let mut rng = rand::thread_rng();
+        let eaten = cafeteria.take_portion(&mut rng);
+        assert!(eaten.contains(&Nutrient::Fiber));
+        assert_eq!(19, cafeteria.nutrients[&Nutrient::Fiber]);
+        cafeteria.discard_portions(19);
+        assert_eq!(0, cafeteria.food);
+        assert!(cafeteria.nutrients.values().all(|n| *n == 0));
+    }
+}
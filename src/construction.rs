@@ -0,0 +1,260 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use sqlx::{PgPool, Row};
+
+use crate::{
+    data::{RoomEffect, RoomType, ROOM_TYPES},
+    db::{
+        bunkers::{self, Bunker, BunkerData, ConstructionProject},
+        inhabitants::{self, Assignment, Inhabitant, SkillType},
+        items, messages,
+    },
+    error, storage,
+    util::skill_roll,
+};
+
+const MAX_PROJECTS: usize = 5;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConstructionRequest {
+    room_type: String,
+    level: i32,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConstructionCancellationRequest {
+    index: usize,
+}
+
+/// Capacity of the bunker before any rooms are built.
+fn get_base_capacity(effect: RoomEffect) -> i32 {
+    match effect {
+        RoomEffect::Crops => 6,
+        RoomEffect::Population => 30,
+        RoomEffect::Storage => 4000,
+        RoomEffect::ColdStorage => 0,
+        RoomEffect::Power => 0,
+    }
+}
+
+pub fn get_capacity(data: &BunkerData, effect: RoomEffect) -> i32 {
+    get_base_capacity(effect)
+        + data
+            .rooms
+            .iter()
+            .filter_map(|(id, level)| ROOM_TYPES.get(id).map(|room_type| (room_type, level)))
+            .filter(|(room_type, _)| room_type.effect == effect)
+            .map(|(room_type, level)| room_type.amount * level)
+            .sum::<i32>()
+}
+
+fn get_room_type(id: &str) -> Result<&'static RoomType, error::Error> {
+    ROOM_TYPES
+        .get(id)
+        .ok_or_else(|| error::client_error("INVALID_ROOM_TYPE"))
+}
+
+pub async fn handle_tick(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    inhabitants: &mut Vec<Inhabitant>,
+) -> Result<(), error::Error> {
+    let workers = inhabitants.iter_mut().filter(|i| {
+        i.is_ready() && !i.data.sleeping && i.data.assignment == Some(Assignment::Construction)
+    });
+    let mut finished = Vec::new();
+    for worker in workers {
+        let project = match bunker.data.construction.first_mut() {
+            Some(project) => project,
+            None => break,
+        };
+        if skill_roll(0.5, worker.get_skill_level(SkillType::Crafting)) {
+            project.progress += 1;
+            worker.add_xp(SkillType::Crafting, 20);
+        }
+        if project.progress >= project.max {
+            finished.push(bunker.data.construction.remove(0));
+        }
+    }
+    let population = inhabitants.len() as i64;
+    for project in finished {
+        // Projects for room types that no longer exist are dropped
+        let room_type = match ROOM_TYPES.get(&project.room_type) {
+            Some(room_type) => room_type,
+            None => continue,
+        };
+        let (subject, body) = if project.level > 0 {
+            bunker
+                .data
+                .rooms
+                .insert(room_type.id.clone(), project.level);
+            (
+                format!("Construction finished: {}", room_type.name),
+                format!(
+                    "The {} has been upgraded to level {}.",
+                    room_type.name.to_lowercase(),
+                    project.level
+                ),
+            )
+        } else {
+            // The room may have been filled up while it was being demolished
+            let current = bunker.data.rooms.get(&room_type.id).copied().unwrap_or(0);
+            if is_in_use(pool, bunker, room_type, current, population).await? {
+                (
+                    format!("Construction cancelled: {}", room_type.name),
+                    format!(
+                        "The {} could not be demolished because it is in use.",
+                        room_type.name.to_lowercase()
+                    ),
+                )
+            } else {
+                bunker.data.rooms.remove(&room_type.id);
+                (
+                    format!("Construction finished: {}", room_type.name),
+                    format!("The {} has been demolished.", room_type.name.to_lowercase()),
+                )
+            }
+        };
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: "Construction team".to_owned(),
+                subject,
+                body,
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Whether removing `level` levels of the room would leave less capacity than is currently used.
+async fn is_in_use(
+    pool: &PgPool,
+    bunker: &Bunker,
+    room_type: &RoomType,
+    level: i32,
+    population: i64,
+) -> Result<bool, error::Error> {
+    let remaining = get_capacity(&bunker.data, room_type.effect) - room_type.amount * level;
+    Ok(match room_type.effect {
+        RoomEffect::Crops => bunker.data.horticulture.crops.len() as i32 > remaining,
+        RoomEffect::Population => population > remaining as i64,
+        RoomEffect::Storage => {
+            storage::get_free_space(pool, bunker).await? < (room_type.amount * level) as f64
+        }
+        RoomEffect::ColdStorage | RoomEffect::Power => false,
+    })
+}
+
+/// Starts building or upgrading a room to the next level, or demolishing it when the requested
+/// level is 0. Materials are only needed for building.
+pub async fn start_construction(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &ConstructionRequest,
+) -> Result<(), error::Error> {
+    let room_type = get_room_type(&request.room_type)?;
+    if bunker.data.construction.len() >= MAX_PROJECTS {
+        Err(error::client_error("TOO_MANY_PROJECTS"))?;
+    }
+    if bunker
+        .data
+        .construction
+        .iter()
+        .any(|p| p.room_type == room_type.id)
+    {
+        Err(error::client_error("ALREADY_UNDER_CONSTRUCTION"))?;
+    }
+    let current = bunker.data.rooms.get(&room_type.id).copied().unwrap_or(0);
+    let mut tx = pool.begin().await?;
+    let labour = if request.level == 0 && current > 0 {
+        let population: i64 = inhabitants::get_inhabitant_count_query(bunker.id)
+            .fetch_one(&mut tx)
+            .await?
+            .try_get(0)?;
+        if is_in_use(pool, bunker, room_type, current, population).await? {
+            Err(error::client_error("ROOM_IN_USE"))?;
+        }
+        room_type.labour / 2
+    } else if request.level == current + 1 && request.level <= room_type.max_level {
+        for (material, quantity) in &room_type.materials {
+            let affected = items::remove_items_query(bunker.id, material, *quantity)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+            if affected < 1 {
+                Err(error::client_error("MISSING_ITEM"))?;
+            }
+        }
+        room_type.labour
+    } else {
+        Err(error::client_error("INVALID_LEVEL"))?
+    };
+    bunker.data.construction.push(ConstructionProject {
+        room_type: room_type.id.clone(),
+        level: request.level,
+        progress: 0,
+        max: labour,
+    });
+    bunkers::update_bunker_data_query(bunker)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    items::remove_empty_items(pool, bunker.id).await?;
+    Ok(())
+}
+
+pub async fn cancel_construction(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &ConstructionCancellationRequest,
+) -> Result<(), error::Error> {
+    if request.index >= bunker.data.construction.len() {
+        Err(error::client_error("OUT_OF_RANGE"))?;
+    }
+    let mut tx = pool.begin().await?;
+    let project = bunker.data.construction.remove(request.index);
+    if let (true, Some(room_type)) = (project.level > 0, ROOM_TYPES.get(&project.room_type)) {
+        for (material, quantity) in &room_type.materials {
+            items::add_item_query(bunker.id, material, *quantity)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    bunkers::update_bunker_data_query(bunker)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_add_capacity() {
+        let mut data = BunkerData::default();
+        let base = get_capacity(&data, RoomEffect::Crops);
+        let room_type = ROOM_TYPES
+            .values()
+            .find(|t| t.effect == RoomEffect::Crops)
+            .expect("No room type with crop slots");
+        data.rooms.insert(room_type.id.clone(), 2);
+        assert_eq!(
+            base + room_type.amount * 2,
+            get_capacity(&data, RoomEffect::Crops)
+        );
+        assert_eq!(
+            get_base_capacity(RoomEffect::Population),
+            get_capacity(&data, RoomEffect::Population)
+        );
+    }
+}
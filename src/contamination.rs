@@ -0,0 +1,189 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    data::ITEM_TYPES,
+    db::{
+        bunkers::Bunker,
+        inhabitants::Inhabitant,
+        items::{self, Item},
+        messages,
+    },
+    error, storage,
+};
+
+pub const SHIELDED_STORAGE_CAPACITY: f64 = 100.0; // litres
+
+/// Contamination needed to add one point of exposure per tick.
+const CONTAMINATION_PER_EXPOSURE: i32 = 20;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateShieldedStorageRequest {
+    item_type: String,
+    quantity: i32,
+}
+
+/// Radioactive items kept in regular storage expose everyone in the bunker.
+pub async fn handle_tick(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    inhabitants: &mut Vec<Inhabitant>,
+) -> Result<(), error::Error> {
+    let contamination = get_contamination(&items::get_items(pool, bunker.id).await?);
+    let existing = bunker.data.shielded_storage.contamination;
+    bunker.data.shielded_storage.contamination = contamination;
+    if contamination > 0 {
+        let mut rng = rand::thread_rng();
+        for inhabitant in inhabitants.iter_mut() {
+            if inhabitant.expedition_id.is_some() {
+                continue;
+            }
+            // Stochastic rounding so that low levels of contamination still add up
+            let exposure = (contamination + rng.gen_range(0..CONTAMINATION_PER_EXPOSURE))
+                / CONTAMINATION_PER_EXPOSURE;
+            if exposure > 0 {
+                inhabitant.data.surface_exposure += exposure;
+                inhabitant.changed = true;
+            }
+        }
+    }
+    if contamination > 0 && existing == 0 {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Radiation monitor"),
+                subject: format!("Radiation detected in storage"),
+                body: format!(
+                    "Radioactive materials in the storage room are contaminating the bunker. \
+                    Move them to shielded storage to protect the inhabitants."
+                ),
+            },
+        )
+        .await?;
+    } else if contamination == 0 && existing > 0 {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Radiation monitor"),
+                subject: format!("Storage radiation cleared"),
+                body: format!("Radiation levels in the storage room are back to normal."),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn update_shielded_storage(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &UpdateShieldedStorageRequest,
+) -> Result<(), error::Error> {
+    let item_type = ITEM_TYPES
+        .get(&request.item_type)
+        .ok_or_else(|| error::client_error("UNKNOWN_ITEM_TYPE"))?;
+    if item_type.radiation < 1 {
+        Err(error::client_error("INVALID_ITEM_TYPE"))?;
+    }
+    storage::update_stock(
+        pool,
+        bunker,
+        &item_type.id,
+        request.quantity,
+        |data| {
+            data.shielded_storage
+                .items
+                .get(&item_type.id)
+                .copied()
+                .unwrap_or(0)
+        },
+        |data| {
+            let items = &mut data.shielded_storage.items;
+            let existing = items.get(&item_type.id).copied().unwrap_or(0);
+            if request.quantity > existing
+                && get_volume_with(items, &item_type.id, request.quantity)
+                    > SHIELDED_STORAGE_CAPACITY
+            {
+                Err(error::client_error("STORAGE_FULL"))?;
+            }
+            if request.quantity > 0 {
+                items.insert(item_type.id.clone(), request.quantity);
+            } else {
+                items.remove(&item_type.id);
+            }
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Total radiation of unshielded items in storage.
+fn get_contamination(items: &[Item]) -> i32 {
+    items
+        .iter()
+        .filter_map(|item| {
+            ITEM_TYPES
+                .get(&item.item_type)
+                .map(|item_type| item_type.radiation * item.quantity)
+        })
+        .sum()
+}
+
+/// Volume of the shielded storage if it held `quantity` of `item_type` in addition to the other
+/// items.
+fn get_volume_with(items: &HashMap<String, i32>, item_type: &str, quantity: i32) -> f64 {
+    items
+        .iter()
+        .filter(|(id, _)| *id != item_type)
+        .chain(std::iter::once((&item_type.to_owned(), &quantity)))
+        .filter_map(|(id, quantity)| ITEM_TYPES.get(id).map(|t| t.volume * *quantity as f64))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(item_type: &str, quantity: i32) -> Item {
+        Item {
+            id: 1,
+            bunker_id: 1,
+            item_type: item_type.to_owned(),
+            quantity,
+        }
+    }
+
+    #[test]
+    fn only_radioactive_items_contaminate() {
+        assert_eq!(
+            0,
+            get_contamination(&[item("fuel-rod-10", 1), item("steel", 5)])
+        );
+        assert_eq!(
+            8 + 3,
+            get_contamination(&[item("depleted-fuel-rod", 2), item("nuclear-material", 3)])
+        );
+    }
+
+    #[test]
+    fn volume_replaces_existing_quantity() {
+        let items = HashMap::from([
+            ("depleted-fuel-rod".to_owned(), 2),
+            ("nuclear-material".to_owned(), 4),
+        ]);
+        assert_eq!(22.0, get_volume_with(&items, "nuclear-material", 4));
+        assert_eq!(20.5, get_volume_with(&items, "nuclear-material", 1));
+        assert_eq!(102.0, get_volume_with(&items, "depleted-fuel-rod", 10));
+        assert!(get_volume_with(&items, "depleted-fuel-rod", 10) > SHIELDED_STORAGE_CAPACITY);
+    }
+}
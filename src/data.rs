@@ -12,6 +12,14 @@ use std::{
 
 use tracing::info;
 
+use crate::{
+    db::{
+        inhabitants::{Assignment, Nutrient, SkillType},
+        worlds::Weather,
+    },
+    weather::Season,
+};
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct LootEntry {
@@ -30,6 +38,70 @@ pub struct LocationType {
     pub quantity: i32,
     #[serde(default)]
     pub loot: HashMap<String, LootEntry>,
+    #[serde(default)]
+    pub recovery_days: f64, // average days for one search to be recovered, 0 means never
+    #[serde(default)]
+    pub single_use: bool,
+    #[serde(default)]
+    pub spawn_days: f64, // average days between new locations appearing, 0 means never
+    #[serde(default)]
+    pub max_spawned: i32, // most undepleted locations of the type at once, 0 means no limit
+    #[serde(default)]
+    pub events: Vec<LocationEvent>,
+    #[serde(default)]
+    pub placement: Placement,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Placement {
+    #[default]
+    Anywhere,
+    Urban, // on urban terrain such as rubble and roads
+    Rural,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct LocationEvent {
+    pub chance: f64, // per search
+    pub text: String,
+    #[serde(default)]
+    pub conditions: Vec<EventCondition>, // all must be met for success
+    #[serde(default)]
+    pub success_text: Option<String>,
+    #[serde(default)]
+    pub success: Vec<EventEffect>,
+    #[serde(default)]
+    pub failure_text: Option<String>,
+    #[serde(default)]
+    pub failure: Vec<EventEffect>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EventCondition {
+    Skill { skill: SkillType, level: i32 }, // any member at or above level
+    SkillRoll { skill: SkillType, chance: f64 }, // best member rolls
+    Item { item_type: String },             // carried or found by the team
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EventEffect {
+    Loot {
+        item_type: String,
+        min: i32,
+        max: i32,
+    },
+    Wound {
+        damage: i32,
+    }, // a random member
+    Xp {
+        skill: SkillType,
+        xp: i32,
+    }, // every member
+    Recruit, // a survivor wants to join the bunker
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -38,6 +110,8 @@ pub struct CraftingRecipe {
     pub min_level: i32,
     pub time: i32, // hours
     pub ingredients: HashMap<String, i32>,
+    #[serde(default)]
+    pub radiation: i32, // exposure risked by the crafter per hour of work
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, Default)]
@@ -68,7 +142,210 @@ pub struct ItemType {
     #[serde(default)]
     pub food: bool,
     #[serde(default)]
+    pub calories: i32, // per unit
+    #[serde(default)]
+    pub nutrients: Vec<Nutrient>,
+    #[serde(default)]
     pub recipe: Option<CraftingRecipe>,
+    #[serde(default)]
+    pub weight: f64, // kg
+    #[serde(default)]
+    pub volume: f64, // litres
+    #[serde(default)]
+    pub value: i32,
+    #[serde(default)]
+    pub carrying_capacity: f64, // kg
+    #[serde(default)]
+    pub carrying_volume: f64, // litres
+    #[serde(default)]
+    pub radiation_protection: i32, // percent
+    #[serde(default)]
+    pub armor: i32, // damage reduction
+    #[serde(default)]
+    pub defense: i32, // bunker fortification
+    #[serde(default)]
+    pub radiation: i32, // contamination when stored outside shielded storage
+    #[serde(default)]
+    pub shelf_life: i32, // days before the item starts to spoil, 0 if it keeps indefinitely
+    #[serde(default)]
+    pub spoilage: f64, // share of the stock lost per day once the shelf life has passed
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TerrainType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub colors: Vec<[u8; 3]>,
+    pub speed: f64,
+    pub exposure: f64,
+    #[serde(default)]
+    pub impassable: bool,
+    #[serde(default)]
+    pub hazard: i32,
+    #[serde(default)]
+    pub water: bool,
+    #[serde(default)]
+    pub urban: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EnemyBehaviour {
+    Aggressive, // always closes in
+    Defensive,  // holds position once in range
+    Cowardly,   // flees when wounded
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct EnemyType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub name_plural: String,
+    pub hp: i32,
+    pub damage: i32,
+    pub range: i32,
+    #[serde(default)]
+    pub accuracy: i32, // skill level
+    #[serde(default)]
+    pub armor: i32,
+    #[serde(default)]
+    pub initiative: i32,
+    pub behaviour: EnemyBehaviour,
+    #[serde(default)]
+    pub biomes: Vec<String>, // terrain types, empty means anywhere
+    #[serde(default)]
+    pub raider: bool, // attacks bunkers
+    #[serde(default)]
+    pub loot: HashMap<String, LootEntry>,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RoomEffect {
+    Crops,       // crop slots in horticulture
+    Population,  // inhabitants
+    Storage,     // litres
+    ColdStorage, // litres
+    Power,       // kW
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MealType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub min_level: i32, // cooking
+    pub ingredients: HashMap<String, i32>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct RoomType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub effect: RoomEffect,
+    pub amount: i32, // per level
+    pub max_level: i32,
+    pub labour: i32,                     // hours per level
+    pub materials: HashMap<String, i32>, // per level
+}
+
+/// Metrics that facilities can report, each is read by the game loop.
+pub const FACILITY_OUTPUTS: &[&str] = &["water_quality", "air_quality"];
+
+/// A facility that draws power, decays and can malfunction. Facilities without a crew or an output,
+/// e.g. a radio room that only draws power, can be added with a TOML file alone. A crewed facility
+/// still needs an [`Assignment`] variant, and a new kind of output needs code that reads it.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct FacilityType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub assignment: Option<Assignment>, // crew that maintains the facility
+    #[serde(default)]
+    pub skill: Option<SkillType>,
+    #[serde(default)]
+    pub decay: i32, // maintenance lost per tick
+    #[serde(default)]
+    pub weather_load: bool, // decay is multiplied by the weather's air load
+    #[serde(default)]
+    pub malfunction_chance: f64,
+    #[serde(default = "default_malfunction_exponent")]
+    pub malfunction_exponent: f64, // shape of the malfunction curve as maintenance drops
+    #[serde(default)]
+    pub power_draw: i32, // kW
+    #[serde(default)]
+    pub power_priority: i32, // default position in the power grid, lowest first
+    #[serde(default)]
+    pub output: Option<String>, // metric reported by the facility, one of FACILITY_OUTPUTS
+    #[serde(default = "default_malfunction_output")]
+    pub malfunction_output: i32, // percent
+    #[serde(default)]
+    pub parts_item: Option<String>,
+    #[serde(default)]
+    pub malfunction_text: String,
+    #[serde(default)]
+    pub repaired_text: String,
+}
+
+fn default_malfunction_exponent() -> f64 {
+    1.0
+}
+
+fn default_malfunction_output() -> i32 {
+    50
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WorldEventType {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub text: String, // announcement, "{sector}" is replaced with the sector of the event
+    #[serde(default)]
+    pub end_text: Option<String>,
+    #[serde(default)]
+    pub chance: f64, // per day
+    pub duration_days: f64,
+    #[serde(default)]
+    pub conditions: Vec<WorldEventCondition>,
+    #[serde(default)]
+    pub effects: Vec<WorldEventEffect>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WorldEventCondition {
+    Season { season: Season },
+    Weather { weather: Weather },
+    MinDays { days: i64 }, // days since the world was created
+    MinBunkers { count: i32 },
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WorldEventEffect {
+    SpawnLocation {
+        location_type: String,
+        #[serde(default)]
+        reveal: bool, // add the location to every bunker's map
+    },
+    Hazard {
+        amount: i32,
+    },
+    Sickness {
+        chance: f64,
+        radius: i32,
+    }, // sectors around the event
 }
 
 lazy_static! {
@@ -80,6 +357,18 @@ lazy_static! {
         load_item_types("data/item").expect("Failed reading item types");
     pub static ref LOCATION_TYPES: HashMap<String, LocationType> =
         load_location_types("data/location").expect("Failed reading location types");
+    pub static ref TERRAIN_TYPES: HashMap<String, TerrainType> =
+        load_terrain_types("data/terrain").expect("Failed reading terrain types");
+    pub static ref ENEMY_TYPES: HashMap<String, EnemyType> =
+        load_enemy_types("data/enemy").expect("Failed reading enemy types");
+    pub static ref WORLD_EVENT_TYPES: HashMap<String, WorldEventType> =
+        load_world_event_types("data/world-event").expect("Failed reading world event types");
+    pub static ref FACILITY_TYPES: HashMap<String, FacilityType> =
+        load_facility_types("data/facility").expect("Failed reading facility types");
+    pub static ref ROOM_TYPES: HashMap<String, RoomType> =
+        load_room_types("data/room").expect("Failed reading room types");
+    pub static ref MEAL_TYPES: HashMap<String, MealType> =
+        load_meal_types("data/meal").expect("Failed reading meal types");
     pub static ref WORLD_MAP: image::DynamicImage = image::io::Reader::open("data/map.png")
         .expect("Failed opening world map")
         .decode()
@@ -91,9 +380,9 @@ fn load_names(path: &str) -> std::io::Result<Vec<String>> {
     Ok(std::io::BufReader::new(file).lines().flatten().collect())
 }
 
-fn load_location_types(dir: &str) -> std::io::Result<HashMap<String, LocationType>> {
-    info!("Reading location types from {}", dir);
-    let mut map = HashMap::new();
+/// Parses every TOML file in a directory, using the file name without the extension as id.
+fn load_toml_dir<T: serde::de::DeserializeOwned>(dir: &str) -> std::io::Result<Vec<(String, T)>> {
+    let mut entries = vec![];
     for entry in read_dir(Path::new(dir))? {
         let entry = entry?;
         let path = entry.path();
@@ -101,29 +390,60 @@ fn load_location_types(dir: &str) -> std::io::Result<HashMap<String, LocationTyp
             let mut file = File::open(&path)?;
             let mut content = String::new();
             file.read_to_string(&mut content)?;
-            let location_type: LocationType = toml::from_str(&content)?;
             let id = path
                 .file_name()
                 .unwrap()
                 .to_str()
                 .unwrap()
                 .replace(".toml", "");
-            for item_type in location_type.loot.keys() {
+            entries.push((id, toml::from_str(&content)?));
+        }
+    }
+    Ok(entries)
+}
+
+fn load_location_types(dir: &str) -> std::io::Result<HashMap<String, LocationType>> {
+    info!("Reading location types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, location_type) in load_toml_dir::<LocationType>(dir)? {
+        for item_type in location_type.loot.keys() {
+            if !ITEM_TYPES.contains_key(item_type) {
+                panic!(
+                    "Unknown item type '{}' in loot table of location '{}'",
+                    item_type, id
+                );
+            }
+        }
+        for event in &location_type.events {
+            let conditions = event.conditions.iter().filter_map(|c| match c {
+                EventCondition::Item { item_type } => Some(item_type),
+                _ => None,
+            });
+            let effects =
+                event
+                    .success
+                    .iter()
+                    .chain(event.failure.iter())
+                    .filter_map(|e| match e {
+                        EventEffect::Loot { item_type, .. } => Some(item_type),
+                        _ => None,
+                    });
+            for item_type in conditions.chain(effects) {
                 if !ITEM_TYPES.contains_key(item_type) {
                     panic!(
-                        "Unknown item type '{}' in loot table of location '{}'",
+                        "Unknown item type '{}' in event of location '{}'",
                         item_type, id
                     );
                 }
             }
-            map.insert(
-                id.clone(),
-                LocationType {
-                    id,
-                    ..location_type
-                },
-            );
         }
+        map.insert(
+            id.clone(),
+            LocationType {
+                id,
+                ..location_type
+            },
+        );
     }
     Ok(map)
 }
@@ -131,22 +451,8 @@ fn load_location_types(dir: &str) -> std::io::Result<HashMap<String, LocationTyp
 fn load_item_types(dir: &str) -> std::io::Result<HashMap<String, ItemType>> {
     info!("Reading item types from {}", dir);
     let mut map = HashMap::new();
-    for entry in read_dir(Path::new(dir))? {
-        let entry = entry?;
-        let path = entry.path();
-        if !path.is_dir() {
-            let mut file = File::open(&path)?;
-            let mut content = String::new();
-            file.read_to_string(&mut content)?;
-            let item_type: ItemType = toml::from_str(&content)?;
-            let id = path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace(".toml", "");
-            map.insert(id.clone(), ItemType { id, ..item_type });
-        }
+    for (id, item_type) in load_toml_dir::<ItemType>(dir)? {
+        map.insert(id.clone(), ItemType { id, ..item_type });
     }
     for item_type in map.values() {
         if let Some(ammo_type) = &item_type.ammo_type {
@@ -165,6 +471,126 @@ fn load_item_types(dir: &str) -> std::io::Result<HashMap<String, ItemType>> {
                 );
             }
         }
+        if let Some(recipe) = &item_type.recipe {
+            for ingredient in recipe.ingredients.keys() {
+                if !map.contains_key(ingredient) {
+                    panic!(
+                        "Unknown ingredient '{}' in item type '{}'",
+                        ingredient, item_type.id
+                    );
+                }
+            }
+        }
+    }
+    Ok(map)
+}
+
+fn load_terrain_types(dir: &str) -> std::io::Result<HashMap<String, TerrainType>> {
+    info!("Reading terrain types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, terrain_type) in load_toml_dir::<TerrainType>(dir)? {
+        if !terrain_type.impassable && terrain_type.speed <= 0.0 {
+            panic!("Passable terrain type '{}' must have a positive speed", id);
+        }
+        map.insert(id.clone(), TerrainType { id, ..terrain_type });
+    }
+    Ok(map)
+}
+
+fn load_enemy_types(dir: &str) -> std::io::Result<HashMap<String, EnemyType>> {
+    info!("Reading enemy types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, enemy_type) in load_toml_dir::<EnemyType>(dir)? {
+        for item_type in enemy_type.loot.keys() {
+            if !ITEM_TYPES.contains_key(item_type) {
+                panic!(
+                    "Unknown item type '{}' in loot table of enemy '{}'",
+                    item_type, id
+                );
+            }
+        }
+        for biome in &enemy_type.biomes {
+            if !TERRAIN_TYPES.contains_key(biome) {
+                panic!("Unknown terrain type '{}' in enemy '{}'", biome, id);
+            }
+        }
+        map.insert(id.clone(), EnemyType { id, ..enemy_type });
+    }
+    Ok(map)
+}
+
+fn load_world_event_types(dir: &str) -> std::io::Result<HashMap<String, WorldEventType>> {
+    info!("Reading world event types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, event_type) in load_toml_dir::<WorldEventType>(dir)? {
+        for effect in &event_type.effects {
+            if let WorldEventEffect::SpawnLocation { location_type, .. } = effect {
+                if !LOCATION_TYPES.contains_key(location_type) {
+                    panic!(
+                        "Unknown location type '{}' in world event '{}'",
+                        location_type, id
+                    );
+                }
+            }
+        }
+        map.insert(id.clone(), WorldEventType { id, ..event_type });
+    }
+    Ok(map)
+}
+
+fn load_facility_types(dir: &str) -> std::io::Result<HashMap<String, FacilityType>> {
+    info!("Reading facility types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, facility_type) in load_toml_dir::<FacilityType>(dir)? {
+        if let Some(parts_item) = &facility_type.parts_item {
+            if !ITEM_TYPES.contains_key(parts_item) {
+                panic!("Unknown parts item '{}' in facility '{}'", parts_item, id);
+            }
+        }
+        if facility_type.assignment.is_some() && facility_type.skill.is_none() {
+            panic!("Missing skill in facility '{}'", id);
+        }
+        if let Some(output) = &facility_type.output {
+            if !FACILITY_OUTPUTS.contains(&output.as_str()) {
+                panic!("Unknown output '{}' in facility '{}'", output, id);
+            }
+        }
+        map.insert(
+            id.clone(),
+            FacilityType {
+                id,
+                ..facility_type
+            },
+        );
+    }
+    Ok(map)
+}
+
+fn load_room_types(dir: &str) -> std::io::Result<HashMap<String, RoomType>> {
+    info!("Reading room types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, room_type) in load_toml_dir::<RoomType>(dir)? {
+        for material in room_type.materials.keys() {
+            if !ITEM_TYPES.contains_key(material) {
+                panic!("Unknown material '{}' in room type '{}'", material, id);
+            }
+        }
+        map.insert(id.clone(), RoomType { id, ..room_type });
+    }
+    Ok(map)
+}
+
+fn load_meal_types(dir: &str) -> std::io::Result<HashMap<String, MealType>> {
+    info!("Reading meal types from {}", dir);
+    let mut map = HashMap::new();
+    for (id, meal_type) in load_toml_dir::<MealType>(dir)? {
+        for ingredient in meal_type.ingredients.keys() {
+            match ITEM_TYPES.get(ingredient) {
+                Some(item_type) if item_type.food => {}
+                _ => panic!("Invalid ingredient '{}' in meal type '{}'", ingredient, id),
+            }
+        }
+        map.insert(id.clone(), MealType { id, ..meal_type });
     }
     Ok(map)
 }
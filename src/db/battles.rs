@@ -0,0 +1,67 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, PgPool, Row};
+
+use crate::{battle::BattleLog, error};
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Battle {
+    pub id: i32,
+    pub bunker_id: i32,
+    pub expedition_id: Option<i32>,
+    pub created: DateTime<Utc>,
+    pub log: Json<BattleLog>,
+}
+
+pub async fn create_battle(
+    pool: &PgPool,
+    bunker_id: i32,
+    expedition_id: Option<i32>,
+    log: &BattleLog,
+) -> Result<i32, error::Error> {
+    Ok(sqlx::query(
+        "INSERT INTO battles (bunker_id, expedition_id, created, log) \
+        VALUES ($1, $2, $3, $4) RETURNING id",
+    )
+    .bind(bunker_id)
+    .bind(expedition_id)
+    .bind(Utc::now())
+    .bind(Json(log))
+    .try_map(|row| row.try_get(0))
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn count_battles_since(
+    pool: &PgPool,
+    bunker_id: i32,
+    since: DateTime<Utc>,
+) -> Result<i32, error::Error> {
+    let count: i64 =
+        sqlx::query("SELECT COUNT(*) FROM battles WHERE bunker_id = $1 AND created > $2")
+            .bind(bunker_id)
+            .bind(since)
+            .try_map(|row| row.try_get(0))
+            .fetch_one(pool)
+            .await?;
+    Ok(count as i32)
+}
+
+pub async fn get_expedition_battles(
+    pool: &PgPool,
+    bunker_id: i32,
+    expedition_id: i32,
+) -> Result<Vec<Battle>, error::Error> {
+    Ok(sqlx::query_as(
+        "SELECT * FROM battles WHERE bunker_id = $1 AND expedition_id = $2 ORDER BY created",
+    )
+    .bind(bunker_id)
+    .bind(expedition_id)
+    .fetch_all(pool)
+    .await?)
+}
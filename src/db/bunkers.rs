@@ -3,11 +3,15 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use sqlx::{postgres::PgArguments, query::Query, types::Json, PgPool, Postgres, Row};
+use sqlx::{postgres::PgArguments, query::Query, types::Json, PgPool, Postgres, Row, Transaction};
 
 use crate::error;
 
+use super::inhabitants::Nutrient;
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Bunker {
@@ -26,9 +30,9 @@ pub struct Bunker {
 #[serde(rename_all = "camelCase")]
 pub struct BunkerData {
     #[serde(default)]
-    pub reactor: ReactorStatus,
+    pub facilities: HashMap<String, FacilityStatus>,
     #[serde(default)]
-    pub water_treatment: WaterTreatmentStatus,
+    pub reactor: ReactorStatus,
     #[serde(default)]
     pub infirmary: InfirmaryStatus,
     #[serde(default)]
@@ -36,44 +40,60 @@ pub struct BunkerData {
     #[serde(default)]
     pub horticulture: HorticultureStatus,
     #[serde(default)]
-    pub air_recycling: AirRecyclingStatus,
-    #[serde(default)]
     pub cafeteria: CafeteriaStatus,
-}
-
-#[derive(serde::Deserialize, serde::Serialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct ReactorStatus {
     #[serde(default)]
-    pub maintenance: i32,
+    pub security: SecurityStatus,
     #[serde(default)]
-    pub fuel: i32,
+    pub power: PowerGridStatus,
     #[serde(default)]
-    pub malfunction: bool,
+    pub shielded_storage: ShieldedStorageStatus,
     #[serde(default)]
-    pub parts: i32,
+    pub storage: StorageStatus,
+    #[serde(default)]
+    pub rooms: HashMap<String, i32>, // room type to level
+    #[serde(default)]
+    pub construction: Vec<ConstructionProject>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct WaterTreatmentStatus {
+pub struct FacilityStatus {
     #[serde(default)]
     pub maintenance: i32,
     #[serde(default)]
     pub malfunction: bool,
     #[serde(default)]
     pub parts: i32,
+    #[serde(default)]
+    pub severe: bool,
+    #[serde(default)]
+    pub downtime: i32, // successful repair rolls spent on a severe malfunction
+    #[serde(default)]
+    pub evacuated: bool, // the crew won't work in the facility
+    #[serde(default)]
+    pub destroyed: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 #[serde(rename_all = "camelCase")]
-pub struct AirRecyclingStatus {
+pub struct ReactorStatus {
     #[serde(default)]
-    pub maintenance: i32,
+    pub fuel: i32,
     #[serde(default)]
-    pub malfunction: bool,
+    pub leak: bool,
     #[serde(default)]
-    pub parts: i32,
+    pub fallout: i32, // ticks of fallout left after a meltdown
+    #[serde(default)]
+    pub evacuation: Evacuation,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Evacuation {
+    #[default]
+    None,
+    ReactorSection, // everyone except the reactor crew
+    Shelter,        // everyone, the reactor is left unattended
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default)]
@@ -93,6 +113,15 @@ pub struct WorkshopProject {
     pub produced: i32,
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConstructionProject {
+    pub room_type: String,
+    pub level: i32, // target level, 0 when demolishing
+    pub progress: i32,
+    pub max: i32,
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct WorkshopStatus {
@@ -106,10 +135,91 @@ pub struct HorticultureStatus {
     pub crops: Vec<Crop>,
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldedStorageStatus {
+    #[serde(default)]
+    pub items: HashMap<String, i32>,
+    #[serde(default)]
+    pub contamination: i32, // radiation from unshielded items in storage
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageStatus {
+    #[serde(default)]
+    pub age: HashMap<String, i32>, // average age in hours of perishable stock
+    #[serde(default)]
+    pub stock: HashMap<String, i32>, // quantity of perishable stock after the last tick
+    #[serde(default)]
+    pub overflow: bool,
+    #[serde(default)]
+    pub spoiled: HashMap<String, i32>, // items spoiled since the last report
+    #[serde(default)]
+    pub spoiled_meals: i32,
+    #[serde(default)]
+    pub report_ticks: i32, // ticks since spoilage was last reported
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct CafeteriaStatus {
-    pub food: i32,
+    pub food: i32, // prepared portions
+    #[serde(default)]
+    pub quality: i32, // average quality of the prepared portions
+    #[serde(default)]
+    pub nutrients: HashMap<Nutrient, i32>, // prepared portions containing each nutrient
+    #[serde(default)]
+    pub meal_plan: Vec<String>, // meal types to cook, all known meals if empty
+    #[serde(default)]
+    pub rationing: Rationing,
+    #[serde(default)]
+    pub priority_groups: Vec<RationGroup>, // fed first and always given full rations
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Rationing {
+    #[default]
+    Full,
+    Reduced,
+    Survival,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RationGroup {
+    Children,
+    Workers,
+    Sick,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityStatus {
+    #[serde(default)]
+    pub last_raid: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub protected_until: Option<DateTime<Utc>>, // safe from other players until then
+    #[serde(default)]
+    pub last_broadcast: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub expeditions: Vec<DateTime<Utc>>, // departures within the last raid cooldown
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerGridStatus {
+    #[serde(default)]
+    pub priorities: Vec<String>, // facility ids, highest priority first
+    #[serde(default)]
+    pub limits: HashMap<String, i32>, // maximum allocation in kW
+    #[serde(default)]
+    pub output: i32, // kW
+    #[serde(default)]
+    pub supply: HashMap<String, i32>, // percent of demand
+    #[serde(default)]
+    pub brownout: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default)]
@@ -123,7 +233,9 @@ pub struct Crop {
     #[serde(default)]
     pub stunted: bool,
     #[serde(default)]
-    pub diseased: bool, // TODO
+    pub diseased: bool,
+    #[serde(default)]
+    pub pests: bool,
 }
 
 pub struct NewBunker {
@@ -150,6 +262,13 @@ pub async fn get_bunker_by_world_and_user(
     )
 }
 
+pub async fn get_bunker(pool: &PgPool, bunker_id: i32) -> Result<Option<Bunker>, error::Error> {
+    Ok(sqlx::query_as("SELECT * FROM bunkers WHERE id = $1")
+        .bind(bunker_id)
+        .fetch_optional(pool)
+        .await?)
+}
+
 pub async fn get_bunker_by_broadcast_id(
     pool: &PgPool,
     broadcast_id: &str,
@@ -192,6 +311,21 @@ pub async fn create_bunker(pool: &PgPool, bunker: &NewBunker) -> Result<i32, err
     .try_get(0)?)
 }
 
+pub async fn get_bunkers(pool: &PgPool, world_id: i32) -> Result<Vec<Bunker>, error::Error> {
+    Ok(sqlx::query_as("SELECT * FROM bunkers WHERE world_id = $1")
+        .bind(world_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+pub async fn get_bunker_ids(pool: &PgPool, world_id: i32) -> Result<Vec<i32>, error::Error> {
+    Ok(sqlx::query("SELECT id FROM bunkers WHERE world_id = $1")
+        .bind(world_id)
+        .try_map(|row| row.try_get(0))
+        .fetch_all(pool)
+        .await?)
+}
+
 pub async fn get_bunkers_by_next_tick(
     pool: &PgPool,
     world_id: i32,
@@ -223,6 +357,54 @@ pub fn update_bunker_data_query(bunker: &Bunker) -> Query<Postgres, PgArguments>
         .bind(&bunker.data)
 }
 
+/// Sets a single field of the security status without writing the rest of the bunker data, which
+/// may have been changed by the bunker's own tick in the meantime.
+pub async fn set_security_field(
+    pool: &PgPool,
+    bunker_id: i32,
+    field: &str,
+    value: serde_json::Value,
+) -> Result<(), error::Error> {
+    sqlx::query(
+        "UPDATE bunkers SET data = jsonb_set(data, '{security}', \
+        COALESCE(data->'security', '{}'::jsonb) || jsonb_build_object($2::text, $3::jsonb)) \
+        WHERE id = $1",
+    )
+    .bind(bunker_id)
+    .bind(field)
+    .bind(Json(value))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub fn add_expedition_departure_query(
+    bunker_id: i32,
+    departure: DateTime<Utc>,
+) -> Query<'static, Postgres, PgArguments> {
+    sqlx::query(
+        "UPDATE bunkers SET data = jsonb_set(data, '{security}', \
+        COALESCE(data->'security', '{}'::jsonb) || jsonb_build_object('expeditions', \
+        COALESCE(data->'security'->'expeditions', '[]'::jsonb) || jsonb_build_array($2::timestamptz))) \
+        WHERE id = $1",
+    )
+    .bind(bunker_id)
+    .bind(departure)
+}
+
+/// Loads the bunker and locks its row until the end of the transaction.
+pub async fn get_bunker_for_update(
+    tx: &mut Transaction<'_, Postgres>,
+    bunker_id: i32,
+) -> Result<Bunker, error::Error> {
+    Ok(
+        sqlx::query_as("SELECT * FROM bunkers WHERE id = $1 FOR UPDATE")
+            .bind(bunker_id)
+            .fetch_one(&mut *tx)
+            .await?,
+    )
+}
+
 pub async fn delete_bunker(pool: &PgPool, bunker_id: i32) -> Result<(), error::Error> {
     sqlx::query("DELETE FROM bunkers WHERE id = $1")
         .bind(bunker_id)
@@ -3,8 +3,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use sqlx::{types::Json, PgPool, Row};
+use sqlx::{postgres::PgArguments, query::Query, types::Json, PgPool, Postgres, Row, Transaction};
 
 use crate::error;
 
@@ -13,6 +15,56 @@ use crate::error;
 pub struct ExpeditionData {
     #[serde(default)]
     pub distance: i32,
+    #[serde(default)]
+    pub arrived: bool,
+    #[serde(default)]
+    pub recalled: bool,
+    #[serde(default)]
+    pub retreat: bool,
+    #[serde(default)]
+    pub report: String,
+    #[serde(default)]
+    pub loot: HashMap<String, i32>,
+    #[serde(default)]
+    pub route: Vec<Waypoint>,
+    #[serde(default = "default_exposure")]
+    pub exposure: f64,
+    #[serde(default)]
+    pub hazard: i32,
+    #[serde(default)]
+    pub tactic: Tactic,
+    #[serde(default)]
+    pub mission: Mission,
+    #[serde(default)]
+    pub recruits: i32, // survivors met who want to join
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Mission {
+    #[default]
+    Scavenge,
+    Scout, // another player's bunker
+    Raid,  // another player's bunker
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Tactic {
+    Aggressive,
+    #[default]
+    Defensive,
+    Avoid,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+pub struct Waypoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+fn default_exposure() -> f64 {
+    1.0
 }
 
 #[derive(serde::Serialize, sqlx::FromRow)]
@@ -57,6 +109,27 @@ pub async fn create_expedition(
     .try_get(0)?)
 }
 
+pub fn update_expedition_query(expedition: &Expedition) -> Query<'_, Postgres, PgArguments> {
+    sqlx::query("UPDATE expeditions SET eta = $2, data = $3 WHERE id = $1")
+        .bind(expedition.id)
+        .bind(expedition.eta)
+        .bind(&expedition.data)
+}
+
+/// Loads the expedition and locks its row until the end of the transaction. Battles referencing
+/// the expedition can still be created while it is locked.
+pub async fn get_expedition_for_update(
+    tx: &mut Transaction<'_, Postgres>,
+    expedition_id: i32,
+) -> Result<Option<Expedition>, error::Error> {
+    Ok(
+        sqlx::query_as("SELECT * FROM expeditions WHERE id = $1 FOR NO KEY UPDATE")
+            .bind(expedition_id)
+            .fetch_optional(&mut *tx)
+            .await?,
+    )
+}
+
 pub async fn delete_expedition(pool: &PgPool, expedition_id: i32) -> Result<(), error::Error> {
     sqlx::query("DELETE FROM expeditions WHERE id = $1")
         .bind(expedition_id)
@@ -77,6 +150,21 @@ pub async fn get_expeditions(
     )
 }
 
+/// Active expeditions that haven't reached their destination yet.
+pub async fn get_outbound_expeditions(
+    pool: &PgPool,
+    world_id: i32,
+) -> Result<Vec<Expedition>, error::Error> {
+    Ok(sqlx::query_as(
+        "SELECT * FROM expeditions WHERE eta > CURRENT_TIMESTAMP \
+            AND NOT COALESCE((data->>'arrived')::boolean, false) \
+            AND bunker_id IN (SELECT id FROM bunkers WHERE world_id = $1)",
+    )
+    .bind(world_id)
+    .fetch_all(pool)
+    .await?)
+}
+
 pub async fn get_finished_expeditions(
     pool: &PgPool,
     world_id: i32,
@@ -3,12 +3,17 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
 use chrono::{Date, Datelike, NaiveDate, NaiveDateTime, Utc};
 use itertools::Itertools;
 use sqlx::{postgres::PgArguments, query::Query, types::Json, PgPool, Postgres, Row};
 
 use crate::error;
 
+/// Morale at or below which inhabitants perform below their skill level.
+pub const LOW_MORALE: i32 = -50;
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Inhabitant {
@@ -22,7 +27,7 @@ pub struct Inhabitant {
     pub changed: bool, // Not in db, used for tracking changes when executing world ticks
 }
 
-#[derive(serde::Serialize, serde::Deserialize, PartialEq, Copy, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Copy, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub enum SkillType {
     Combat,
@@ -58,7 +63,7 @@ pub const SKILL_TYPES: &[SkillType] = &[
     SkillType::Crafting,
 ];
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Skill {
     pub skill_type: SkillType,
@@ -66,6 +71,24 @@ pub struct Skill {
     pub xp: i32,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Nutrient {
+    Protein,
+    Fat,
+    Fiber,
+    Vitamins,
+    Minerals,
+}
+
+pub const NUTRIENTS: [Nutrient; 5] = [
+    Nutrient::Protein,
+    Nutrient::Fat,
+    Nutrient::Fiber,
+    Nutrient::Vitamins,
+    Nutrient::Minerals,
+];
+
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Copy, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum Assignment {
@@ -76,6 +99,8 @@ pub enum Assignment {
     WaterTreatment,
     AirRecycling,
     Cafeteria,
+    Security,
+    Construction,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default)]
@@ -96,6 +121,12 @@ pub struct InhabitantData {
     #[serde(default)]
     pub ammo: i32,
     #[serde(default)]
+    pub pack_type: Option<String>,
+    #[serde(default)]
+    pub outfit_type: Option<String>,
+    #[serde(default)]
+    pub armor_type: Option<String>,
+    #[serde(default)]
     pub hp: i32,
     #[serde(default)]
     pub tiredness: i32, // TODO: sleep state etc
@@ -114,9 +145,21 @@ pub struct InhabitantData {
     #[serde(default)]
     pub starving: bool,
     #[serde(default)]
+    pub underfed: i32, // hunger left over from smaller rations
+    #[serde(default)]
+    pub nutrition: HashMap<Nutrient, i32>, // hours since each nutrient was last eaten
+    #[serde(default)]
+    pub malnourished: bool,
+    #[serde(default)]
+    pub morale: i32, // -100 to 100, 0 is content
+    #[serde(default)]
     pub sleeping: bool,
     #[serde(default)]
     pub sleep_block: i32,
+    #[serde(default)]
+    pub contagious: bool, // sickness spreads to others
+    #[serde(default)]
+    pub quarantined: bool,
 }
 
 pub struct NewInhabitant {
@@ -125,12 +168,11 @@ pub struct NewInhabitant {
     pub data: InhabitantData,
 }
 
-pub async fn create_inhabitant(
-    pool: &PgPool,
+pub fn create_inhabitant_query(
     bunker_id: i32,
     inhabitant: &NewInhabitant,
-) -> Result<i32, error::Error> {
-    Ok(sqlx::query(
+) -> Query<'_, Postgres, PgArguments> {
+    sqlx::query(
         "INSERT INTO inhabitants (bunker_id, expedition_id, name, date_of_birth, data) \
         VALUES ($1, NULL, $2, $3, $4) RETURNING id",
     )
@@ -138,9 +180,17 @@ pub async fn create_inhabitant(
     .bind(&inhabitant.name)
     .bind(inhabitant.date_of_birth)
     .bind(Json(&inhabitant.data))
-    .fetch_one(pool)
-    .await?
-    .try_get(0)?)
+}
+
+pub async fn create_inhabitant(
+    pool: &PgPool,
+    bunker_id: i32,
+    inhabitant: &NewInhabitant,
+) -> Result<i32, error::Error> {
+    Ok(create_inhabitant_query(bunker_id, inhabitant)
+        .fetch_one(pool)
+        .await?
+        .try_get(0)?)
 }
 
 pub async fn get_inhabitant(
@@ -169,14 +219,15 @@ pub async fn get_inhabitants(
     )
 }
 
+pub fn get_inhabitant_count_query(bunker_id: i32) -> Query<'static, Postgres, PgArguments> {
+    sqlx::query("SELECT COUNT(*) FROM inhabitants WHERE bunker_id = $1").bind(bunker_id)
+}
+
 pub async fn get_inhabitant_count(pool: &PgPool, bunker_id: i32) -> Result<i64, error::Error> {
-    Ok(
-        sqlx::query("SELECT COUNT(*) FROM inhabitants WHERE bunker_id = $1")
-            .bind(bunker_id)
-            .fetch_one(pool)
-            .await?
-            .try_get(0)?,
-    )
+    Ok(get_inhabitant_count_query(bunker_id)
+        .fetch_one(pool)
+        .await?
+        .try_get(0)?)
 }
 
 pub async fn get_by_expedition(
@@ -251,6 +302,7 @@ pub async fn delete_inhabitant(pool: &PgPool, inhabitant_id: i32) -> Result<(),
 impl Inhabitant {
     pub fn is_ready(&self) -> bool {
         self.expedition_id.is_none()
+            && !self.data.quarantined
             && !self.data.bleeding
             && !self.data.infection
             && !self.data.wounded
@@ -259,15 +311,21 @@ impl Inhabitant {
             && (!self.data.starving || self.data.health > 33)
     }
 
+    /// Effective skill level, demoralized inhabitants perform one level below their skill.
     pub fn get_skill_level(&self, skill_type: SkillType) -> i32 {
-        get_skill_level(
+        let level = get_skill_level(
             self.data
                 .skills
                 .iter()
                 .find(|s| s.skill_type == skill_type)
                 .map(|s| s.xp)
                 .unwrap_or_else(|| 0),
-        )
+        );
+        if self.data.morale <= LOW_MORALE {
+            (level - 1).max(0)
+        } else {
+            level
+        }
     }
 
     pub fn add_xp(&mut self, skill_type: SkillType, xp: i32) -> bool {
@@ -296,16 +354,6 @@ impl Inhabitant {
     }
 }
 
-#[deprecated]
-pub fn get_inhabitant_skill_level(inhabitant: &Inhabitant, skill_type: SkillType) -> i32 {
-    inhabitant.get_skill_level(skill_type)
-}
-
-#[deprecated]
-pub fn add_xp_to_skill(inhabitant: &mut Inhabitant, skill_type: SkillType, xp: i32) -> bool {
-    inhabitant.add_xp(skill_type, xp)
-}
-
 pub fn get_skill_level(xp: i32) -> i32 {
     ((xp as f64) / 50.0 + 1.0).log2() as i32
 }
@@ -332,6 +380,20 @@ pub fn get_age(now: NaiveDateTime, dob: NaiveDate) -> i32 {
     }
 }
 
+/// Inhabitant of bunker 1 born in 2000, for tests.
+#[cfg(test)]
+pub fn test_inhabitant(id: i32, data: InhabitantData) -> Inhabitant {
+    Inhabitant {
+        id,
+        bunker_id: 1,
+        expedition_id: None,
+        name: format!("Inhabitant {}", id),
+        date_of_birth: NaiveDate::from_ymd(2000, 1, 1),
+        data: Json(data),
+        changed: false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -356,4 +418,23 @@ mod tests {
         assert_eq!(10, get_age(now, NaiveDate::from_ymd(2010, 02, 28)));
         assert_eq!(8, get_age(now, NaiveDate::from_ymd(2012, 02, 29)));
     }
+
+    #[test]
+    fn low_morale_lowers_skill_level() {
+        let mut inhabitant = test_inhabitant(
+            1,
+            InhabitantData {
+                skills: vec![Skill {
+                    skill_type: SkillType::Cooking,
+                    level: 2,
+                    xp: 150,
+                }],
+                ..Default::default()
+            },
+        );
+        assert_eq!(2, inhabitant.get_skill_level(SkillType::Cooking));
+        inhabitant.data.morale = LOW_MORALE;
+        assert_eq!(1, inhabitant.get_skill_level(SkillType::Cooking));
+        assert_eq!(0, inhabitant.get_skill_level(SkillType::Repair));
+    }
 }
@@ -3,6 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use chrono::{DateTime, Utc};
 use sqlx::{types::Json, PgPool, Row};
 
 use crate::error;
@@ -13,6 +14,10 @@ pub struct LocationData {
     pub location_type: String,
     #[serde(default)]
     pub searches: i32,
+    #[serde(default)]
+    pub bunker_id: Option<i32>, // location of a player's bunker
+    #[serde(default)]
+    pub depleted: bool, // single-use location that has been searched
 }
 
 #[derive(serde::Serialize, sqlx::FromRow)]
@@ -39,6 +44,8 @@ pub struct NewLocation {
 pub struct Sector {
     pub x: i32,
     pub y: i32,
+    pub hazard: i32,
+    pub last_visited: Option<DateTime<Utc>>,
 }
 
 pub async fn create_location(pool: &PgPool, location: &NewLocation) -> Result<i32, error::Error> {
@@ -88,7 +95,7 @@ pub async fn add_all_bunker_locations_in_sector(
 ) -> Result<(), error::Error> {
     sqlx::query(
         "INSERT INTO bunker_locations (bunker_id, location_id) SELECT $1, id FROM locations \
-        WHERE x BETWEEN $2 AND $3 AND y BETWEEN $4 AND $5 AND world_id = $5 \
+        WHERE x BETWEEN $2 AND $3 AND y BETWEEN $4 AND $5 AND world_id = $6 \
         ON CONFLICT DO NOTHING",
     )
     .bind(bunker_id)
@@ -120,6 +127,24 @@ pub async fn add_bunker_sector(
     Ok(())
 }
 
+pub async fn set_sector_visited(
+    pool: &PgPool,
+    bunker_id: i32,
+    x: i32,
+    y: i32,
+) -> Result<(), error::Error> {
+    sqlx::query(
+        "UPDATE bunker_sectors SET last_visited = $4 WHERE bunker_id = $1 AND x = $2 AND y = $3",
+    )
+    .bind(bunker_id)
+    .bind(x)
+    .bind(y)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn get_undiscovered_locations(
     pool: &PgPool,
     bunker_id: i32,
@@ -129,6 +154,7 @@ pub async fn get_undiscovered_locations(
     Ok(sqlx::query_as(
         "SELECT l.* FROM locations l \
         WHERE l.id NOT IN (SELECT bl.location_id FROM bunker_locations bl WHERE bl.bunker_id = $1) \
+        AND NOT COALESCE((l.data->>'depleted')::boolean, false) \
         AND x BETWEEN $2 AND $3 AND y BETWEEN $4 AND $5 \
         AND l.world_id = (SELECT world_id FROM bunkers WHERE id = $1)",
     )
@@ -147,7 +173,7 @@ pub async fn get_discovered_locations(
 ) -> Result<Vec<Location>, error::Error> {
     Ok(sqlx::query_as(
         "SELECT l.* FROM locations l INNER JOIN bunker_locations bl ON bl.location_id = l.id \
-        WHERE bl.bunker_id = $1",
+        WHERE bl.bunker_id = $1 AND NOT COALESCE((l.data->>'depleted')::boolean, false)",
     )
     .bind(bunker_id)
     .fetch_all(pool)
@@ -158,12 +184,16 @@ pub async fn get_explored_sectors(
     pool: &PgPool,
     bunker_id: i32,
 ) -> Result<Vec<Sector>, error::Error> {
-    Ok(
-        sqlx::query_as("SELECT x, y FROM bunker_sectors WHERE bunker_id = $1")
-            .bind(bunker_id)
-            .fetch_all(pool)
-            .await?,
+    Ok(sqlx::query_as(
+        "SELECT bs.x, bs.y, COALESCE(ws.hazard, 0) AS hazard, bs.last_visited \
+            FROM bunker_sectors bs \
+            INNER JOIN bunkers b ON b.id = bs.bunker_id \
+            LEFT JOIN world_sectors ws ON ws.world_id = b.world_id AND ws.x = bs.x AND ws.y = bs.y \
+            WHERE bs.bunker_id = $1",
     )
+    .bind(bunker_id)
+    .fetch_all(pool)
+    .await?)
 }
 
 pub async fn is_location_discovered(
@@ -181,6 +211,81 @@ pub async fn is_location_discovered(
     )
 }
 
+pub async fn get_searched_locations(
+    pool: &PgPool,
+    world_id: i32,
+) -> Result<Vec<Location>, error::Error> {
+    Ok(sqlx::query_as(
+        "SELECT * FROM locations WHERE world_id = $1 \
+        AND COALESCE((data->>'searches')::int, 0) > 0 \
+        AND NOT COALESCE((data->>'depleted')::boolean, false)",
+    )
+    .bind(world_id)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Number of locations of the type that haven't been depleted.
+pub async fn count_locations_of_type(
+    pool: &PgPool,
+    world_id: i32,
+    location_type: &str,
+) -> Result<i32, error::Error> {
+    let count: i64 = sqlx::query(
+        "SELECT COUNT(*) FROM locations WHERE world_id = $1 AND data->>'locationType' = $2 \
+        AND NOT COALESCE((data->>'depleted')::boolean, false)",
+    )
+    .bind(world_id)
+    .bind(location_type)
+    .try_map(|row| row.try_get(0))
+    .fetch_one(pool)
+    .await?;
+    Ok(count as i32)
+}
+
+pub async fn get_location_names_of_type(
+    pool: &PgPool,
+    world_id: i32,
+    location_type: &str,
+) -> Result<Vec<String>, error::Error> {
+    Ok(
+        sqlx::query(
+            "SELECT name FROM locations WHERE world_id = $1 AND data->>'locationType' = $2",
+        )
+        .bind(world_id)
+        .bind(location_type)
+        .try_map(|row| row.try_get(0))
+        .fetch_all(pool)
+        .await?,
+    )
+}
+
+/// Deletes depleted locations that no expedition is heading to.
+pub async fn delete_depleted_locations(pool: &PgPool, world_id: i32) -> Result<(), error::Error> {
+    sqlx::query(
+        "DELETE FROM locations WHERE world_id = $1 \
+        AND COALESCE((data->>'depleted')::boolean, false) \
+        AND NOT EXISTS (SELECT 1 FROM expeditions WHERE location_id = locations.id)",
+    )
+    .bind(world_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_bunkers_with_discovered_location(
+    pool: &PgPool,
+    location_id: i32,
+) -> Result<Vec<i32>, error::Error> {
+    Ok(
+        sqlx::query("SELECT bunker_id FROM bunker_locations WHERE location_id = $1")
+            .bind(location_id)
+            .try_map(|row| row.try_get(0))
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
 pub async fn get_location(pool: &PgPool, location_id: i32) -> Result<Location, error::Error> {
     Ok(sqlx::query_as("SELECT * FROM locations WHERE id = $1")
         .bind(location_id)
@@ -3,12 +3,15 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+pub mod battles;
 pub mod bunkers;
 pub mod expeditions;
 pub mod inhabitants;
 pub mod items;
 pub mod locations;
 pub mod messages;
+pub mod recruits;
+pub mod sectors;
 pub mod sessions;
 pub mod users;
 pub mod worlds;
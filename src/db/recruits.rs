@@ -0,0 +1,71 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{postgres::PgArguments, query::Query, types::Json, PgPool, Postgres, Row};
+
+use crate::error;
+
+use super::inhabitants::{InhabitantData, NewInhabitant};
+
+#[derive(sqlx::FromRow)]
+pub struct Recruit {
+    pub id: i32,
+    pub name: String,
+    pub date_of_birth: NaiveDate,
+    pub created: DateTime<Utc>,
+    pub data: Json<InhabitantData>,
+}
+
+pub async fn create_recruit(
+    pool: &PgPool,
+    bunker_id: i32,
+    recruit: &NewInhabitant,
+) -> Result<i32, error::Error> {
+    Ok(sqlx::query(
+        "INSERT INTO recruits (bunker_id, name, date_of_birth, created, data) \
+        VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(bunker_id)
+    .bind(&recruit.name)
+    .bind(recruit.date_of_birth)
+    .bind(Utc::now())
+    .bind(Json(&recruit.data))
+    .try_map(|row| row.try_get(0))
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn get_recruits(pool: &PgPool, bunker_id: i32) -> Result<Vec<Recruit>, error::Error> {
+    Ok(
+        sqlx::query_as("SELECT * FROM recruits WHERE bunker_id = $1 ORDER BY created")
+            .bind(bunker_id)
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+pub async fn get_recruit(
+    pool: &PgPool,
+    bunker_id: i32,
+    recruit_id: i32,
+) -> Result<Option<Recruit>, error::Error> {
+    Ok(
+        sqlx::query_as("SELECT * FROM recruits WHERE bunker_id = $1 AND id = $2")
+            .bind(bunker_id)
+            .bind(recruit_id)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+pub fn delete_recruit_query(recruit_id: i32) -> Query<'static, Postgres, PgArguments> {
+    sqlx::query("DELETE FROM recruits WHERE id = $1").bind(recruit_id)
+}
+
+pub async fn delete_recruit(pool: &PgPool, recruit_id: i32) -> Result<(), error::Error> {
+    delete_recruit_query(recruit_id).execute(pool).await?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use sqlx::{PgPool, Row};
+
+use crate::error;
+
+pub async fn set_sector_hazard(
+    pool: &PgPool,
+    world_id: i32,
+    x: i32,
+    y: i32,
+    hazard: i32,
+) -> Result<(), error::Error> {
+    sqlx::query(
+        "INSERT INTO world_sectors (world_id, x, y, hazard) VALUES ($1, $2, $3, $4) \
+        ON CONFLICT (world_id, x, y) DO UPDATE SET hazard = EXCLUDED.hazard",
+    )
+    .bind(world_id)
+    .bind(x)
+    .bind(y)
+    .bind(hazard)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_sector_hazard(
+    pool: &PgPool,
+    world_id: i32,
+    x: i32,
+    y: i32,
+) -> Result<i32, error::Error> {
+    Ok(
+        sqlx::query("SELECT hazard FROM world_sectors WHERE world_id = $1 AND x = $2 AND y = $3")
+            .bind(world_id)
+            .bind(x)
+            .bind(y)
+            .try_map(|row| row.try_get(0))
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(0),
+    )
+}
+
+pub async fn decay_hazards(pool: &PgPool, world_id: i32, amount: i32) -> Result<(), error::Error> {
+    sqlx::query(
+        "UPDATE world_sectors SET hazard = GREATEST(hazard - $2, 0) \
+        WHERE world_id = $1 AND hazard > 0",
+    )
+    .bind(world_id)
+    .bind(amount)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_bunkers_with_explored_sector(
+    pool: &PgPool,
+    world_id: i32,
+    x: i32,
+    y: i32,
+) -> Result<Vec<i32>, error::Error> {
+    Ok(sqlx::query(
+        "SELECT bs.bunker_id FROM bunker_sectors bs \
+        INNER JOIN bunkers b ON b.id = bs.bunker_id \
+        WHERE b.world_id = $1 AND bs.x = $2 AND bs.y = $3",
+    )
+    .bind(world_id)
+    .bind(x)
+    .bind(y)
+    .try_map(|row| row.try_get(0))
+    .fetch_all(pool)
+    .await?)
+}
@@ -4,7 +4,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
-use sqlx::{PgPool, Row};
+use sqlx::{types::Json, PgPool, Row};
 
 use crate::error;
 
@@ -20,6 +20,7 @@ pub struct World {
     pub start_year: i32,
     pub time_acceleration: i32,
     pub time_offset: i32,
+    pub pvp: bool,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
@@ -30,6 +31,8 @@ pub struct NewWorld {
     pub start_year: i32,
     pub time_acceleration: i32,
     pub time_offset: i32,
+    #[serde(default)]
+    pub pvp: bool,
 }
 
 #[derive(sqlx::FromRow)]
@@ -39,11 +42,84 @@ pub struct WorldTime {
     pub start_year: i32,
     pub time_acceleration: i32,
     pub time_offset: i32,
+    pub data: Json<WorldData>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WorldData {
+    #[serde(default)]
+    pub next_fallout: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub next_location_update: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub weather: Weather,
+    #[serde(default)]
+    pub forecast: Weather,
+    #[serde(default)]
+    pub next_weather_change: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub events: Vec<ActiveWorldEvent>,
+    #[serde(default)]
+    pub next_event_check: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveWorldEvent {
+    pub event_type: String,
+    pub sector_x: i32,
+    pub sector_y: i32,
+    pub ends: DateTime<Utc>,
+    #[serde(default)]
+    pub location_ids: Vec<i32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Weather {
+    #[default]
+    Clear,
+    Storm,
+    AcidRain,
+    HeatWave,
+    Blizzard,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MapParams {
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "default_size")]
+    pub size: u32, // pixels
+    #[serde(default = "default_water_level")]
+    pub water_level: f64, // fraction of the map covered by water
+    #[serde(default = "default_cities")]
+    pub cities: i32,
+    #[serde(default = "default_hotspots")]
+    pub hotspots: i32,
+}
+
+fn default_size() -> u32 {
+    416
+}
+
+fn default_water_level() -> f64 {
+    0.25
+}
+
+fn default_cities() -> i32 {
+    12
+}
+
+fn default_hotspots() -> i32 {
+    4
 }
 
 pub async fn get_worlds(pool: &PgPool, user_id: i64) -> Result<Vec<World>, error::Error> {
     Ok(sqlx::query_as(
-        "SELECT id, name, open, created, start_year, time_acceleration, time_offset, \
+        "SELECT id, name, open, created, start_year, time_acceleration, time_offset, pvp, \
             (SELECT COUNT(*) FROM bunkers WHERE world_id = worlds.id) players, \
             EXISTS (SELECT 1 FROM bunkers WHERE world_id = worlds.id AND user_id = $1) joined \
             FROM worlds ORDER BY id DESC",
@@ -55,7 +131,7 @@ pub async fn get_worlds(pool: &PgPool, user_id: i64) -> Result<Vec<World>, error
 
 pub async fn get_world(pool: &PgPool, world_id: i32) -> Result<World, error::Error> {
     Ok(sqlx::query_as(
-        "SELECT id, name, open, created, start_year, time_acceleration, time_offset, \
+        "SELECT id, name, open, created, start_year, time_acceleration, time_offset, pvp, \
             (SELECT COUNT(*) FROM bunkers WHERE world_id = worlds.id) players, \
             EXISTS (SELECT 1 FROM bunkers WHERE world_id = worlds.id AND user_id = $1) joined \
             FROM worlds WHERE id = $1",
@@ -68,6 +144,7 @@ pub async fn get_world(pool: &PgPool, world_id: i32) -> Result<World, error::Err
 pub async fn get_user_worlds(pool: &PgPool, user_id: i64) -> Result<Vec<World>, error::Error> {
     Ok(sqlx::query_as(
         "SELECT w.id, w.name, w.open, w.created, w.start_year, w.time_acceleration, time_offset, \
+            w.pvp, \
             (SELECT COUNT(*) FROM bunkers WHERE world_id = w.id) players, \
             true AS joined \
             FROM worlds w \
@@ -82,8 +159,8 @@ pub async fn get_user_worlds(pool: &PgPool, user_id: i64) -> Result<Vec<World>,
 
 pub async fn create_world(pool: &PgPool, data: &NewWorld) -> Result<i32, error::Error> {
     let id = sqlx::query(
-        "INSERT INTO worlds (name, open, start_year, time_acceleration, time_offset, created) \
-        VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        "INSERT INTO worlds (name, open, start_year, time_acceleration, time_offset, created, pvp) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
     )
     .bind(&data.name)
     .bind(data.open)
@@ -91,6 +168,7 @@ pub async fn create_world(pool: &PgPool, data: &NewWorld) -> Result<i32, error::
     .bind(data.time_acceleration)
     .bind(data.time_offset)
     .bind(Utc::now())
+    .bind(data.pvp)
     .fetch_one(pool)
     .await?
     .try_get(0)?;
@@ -99,7 +177,7 @@ pub async fn create_world(pool: &PgPool, data: &NewWorld) -> Result<i32, error::
 
 pub async fn get_world_time(pool: &PgPool, world_id: i32) -> Result<WorldTime, error::Error> {
     Ok(sqlx::query_as(
-        "SELECT id, created, start_year, time_acceleration, time_offset \
+        "SELECT id, created, start_year, time_acceleration, time_offset, data \
             FROM worlds WHERE id = $1",
     )
     .bind(world_id)
@@ -109,13 +187,80 @@ pub async fn get_world_time(pool: &PgPool, world_id: i32) -> Result<WorldTime, e
 
 pub async fn get_world_times(pool: &PgPool) -> Result<Vec<WorldTime>, error::Error> {
     Ok(sqlx::query_as(
-        "SELECT id, created, start_year, time_acceleration, time_offset \
+        "SELECT id, created, start_year, time_acceleration, time_offset, data \
             FROM worlds",
     )
     .fetch_all(pool)
     .await?)
 }
 
+/// Writes the given top-level fields of the world data. The other fields are left as they are in
+/// the database, since they may be updated concurrently by other parts of the game loop or by
+/// admins.
+pub async fn update_world_data(
+    pool: &PgPool,
+    world: &WorldTime,
+    fields: &[&str],
+) -> Result<(), error::Error> {
+    let data = serde_json::to_value(&world.data.0)
+        .map_err(|_| error::internal_error("Failed serializing world data"))?;
+    let patch: serde_json::Map<String, serde_json::Value> = fields
+        .iter()
+        .filter_map(|field| Some((field.to_string(), data.get(field)?.clone())))
+        .collect();
+    sqlx::query("UPDATE worlds SET data = data || $2 WHERE id = $1")
+        .bind(world.id)
+        .bind(Json(patch))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Appends an event to the active events without overwriting any other changes to the list.
+pub async fn add_world_event(
+    pool: &PgPool,
+    world_id: i32,
+    event: &ActiveWorldEvent,
+) -> Result<(), error::Error> {
+    sqlx::query(
+        "UPDATE worlds SET data = jsonb_set(data, '{events}', \
+        COALESCE(data->'events', '[]'::jsonb) || jsonb_build_array($2::jsonb)) WHERE id = $1",
+    )
+    .bind(world_id)
+    .bind(Json(event))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn create_world_map(
+    pool: &PgPool,
+    world_id: i32,
+    params: &MapParams,
+    image: &[u8],
+) -> Result<(), error::Error> {
+    sqlx::query("INSERT INTO world_maps (world_id, params, image) VALUES ($1, $2, $3)")
+        .bind(world_id)
+        .bind(Json(params))
+        .bind(image)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_world_map_image(
+    pool: &PgPool,
+    world_id: i32,
+) -> Result<Option<Vec<u8>>, error::Error> {
+    Ok(
+        sqlx::query("SELECT image FROM world_maps WHERE world_id = $1")
+            .bind(world_id)
+            .try_map(|row| row.try_get(0))
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
 impl World {
     pub fn now(&self) -> NaiveDateTime {
         let duration = Utc::now().signed_duration_since(self.created);
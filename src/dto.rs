@@ -3,20 +3,25 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
 use chrono::{DateTime, NaiveDate, Utc};
 
 use crate::{
     data::{get_item_type, ItemType},
     db::{
         bunkers::{
-            AirRecyclingStatus, Bunker, CafeteriaStatus, HorticultureStatus, InfirmaryStatus,
-            ReactorStatus, WaterTreatmentStatus, WorkshopStatus,
+            Bunker, CafeteriaStatus, ConstructionProject, FacilityStatus, HorticultureStatus,
+            InfirmaryStatus, PowerGridStatus, ReactorStatus, SecurityStatus, ShieldedStorageStatus,
+            StorageStatus, WorkshopStatus,
         },
-        expeditions::Expedition,
+        expeditions::{Expedition, Mission, Tactic, Waypoint},
         inhabitants::{Assignment, Inhabitant, Skill},
         items::Item,
         locations::Location,
+        recruits::Recruit,
     },
+    health,
 };
 
 #[derive(serde::Serialize)]
@@ -31,16 +36,24 @@ pub struct InhabitantDto {
     pub team: Option<String>,
     pub weapon_type: Option<String>,
     pub ammo: i32,
+    pub pack_type: Option<String>,
+    pub outfit_type: Option<String>,
+    pub armor_type: Option<String>,
     pub bleeding: bool,
     pub wounded: bool,
     pub sick: bool,
     pub infection: bool,
     pub recovering: bool,
     pub starving: bool,
+    pub underfed: bool,
+    pub malnourished: bool,
+    pub contagious: bool,
     pub sleeping: bool,
+    pub quarantined: bool,
     pub tired: bool,
     pub ready: bool,
     pub health: i32,
+    pub morale: i32,
 }
 
 impl From<Inhabitant> for InhabitantDto {
@@ -57,16 +70,24 @@ impl From<Inhabitant> for InhabitantDto {
             team: data.team,
             weapon_type: data.weapon_type,
             ammo: data.ammo,
+            pack_type: data.pack_type,
+            outfit_type: data.outfit_type,
+            armor_type: data.armor_type,
             bleeding: data.bleeding,
             wounded: data.wounded,
             sick: data.sick,
             infection: data.infection,
             recovering: data.recovering,
             starving: data.starving,
+            underfed: data.underfed >= health::MEAL_HOURS,
+            malnourished: data.malnourished,
+            contagious: data.contagious,
             sleeping: data.sleeping,
+            quarantined: data.quarantined,
             tired: data.tiredness > 16,
             ready,
             health: data.health,
+            morale: data.morale,
         }
     }
 }
@@ -80,7 +101,7 @@ pub struct LocationDto {
     pub x: i32,
     pub y: i32,
     pub location_type: String,
-    // TODO: abundance
+    pub searches: i32,
 }
 
 impl From<Location> for LocationDto {
@@ -93,6 +114,7 @@ impl From<Location> for LocationDto {
             x: source.x,
             y: source.y,
             location_type: data.location_type,
+            searches: data.searches,
         }
     }
 }
@@ -125,6 +147,11 @@ pub struct ExpeditionDto {
     pub eta: DateTime<Utc>,
     pub created: DateTime<Utc>,
     pub distance: i32,
+    pub arrived: bool,
+    pub recalled: bool,
+    pub route: Vec<Waypoint>,
+    pub tactic: Tactic,
+    pub mission: Mission,
 }
 
 impl From<Expedition> for ExpeditionDto {
@@ -138,10 +165,53 @@ impl From<Expedition> for ExpeditionDto {
             eta: source.eta,
             created: source.created,
             distance: data.distance,
+            arrived: data.arrived,
+            recalled: data.recalled,
+            route: data.route,
+            tactic: data.tactic,
+            mission: data.mission,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecruitDto {
+    pub id: i32,
+    pub name: String,
+    pub date_of_birth: NaiveDate,
+    pub created: DateTime<Utc>,
+    pub skills: Vec<Skill>,
+    pub health: i32,
+    pub sick: bool,
+    pub surface_exposure: i32,
+}
+
+impl From<Recruit> for RecruitDto {
+    fn from(source: Recruit) -> RecruitDto {
+        let data = source.data.0;
+        RecruitDto {
+            id: source.id,
+            name: source.name,
+            date_of_birth: source.date_of_birth,
+            created: source.created,
+            skills: data.skills,
+            health: data.health,
+            sick: data.sick,
+            surface_exposure: data.surface_exposure,
         }
     }
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactorDto {
+    #[serde(flatten)]
+    pub facility: FacilityStatus,
+    #[serde(flatten)]
+    pub status: ReactorStatus,
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BunkerDto {
@@ -150,31 +220,49 @@ pub struct BunkerDto {
     pub x: i32,
     pub y: i32,
     pub broadcast_id: String,
-    pub reactor: ReactorStatus,
-    pub water_treatment: WaterTreatmentStatus,
+    pub facilities: HashMap<String, FacilityStatus>,
+    pub reactor: ReactorDto,
+    pub water_treatment: FacilityStatus,
     pub infirmary: InfirmaryStatus,
     pub workshop: WorkshopStatus,
     pub horticulture: HorticultureStatus,
-    pub air_recycling: AirRecyclingStatus,
+    pub air_recycling: FacilityStatus,
     pub cafeteria: CafeteriaStatus,
+    pub security: SecurityStatus,
+    pub power: PowerGridStatus,
+    pub shielded_storage: ShieldedStorageStatus,
+    pub storage: StorageStatus,
+    pub rooms: HashMap<String, i32>,
+    pub construction: Vec<ConstructionProject>,
 }
 
 impl From<Bunker> for BunkerDto {
     fn from(source: Bunker) -> BunkerDto {
         let data = source.data.0;
+        let get_facility = |id: &str| data.facilities.get(id).cloned().unwrap_or_default();
         BunkerDto {
             id: source.id,
             number: source.number,
             x: source.x,
             y: source.y,
             broadcast_id: source.broadcast_id,
-            reactor: data.reactor,
-            water_treatment: data.water_treatment,
+            reactor: ReactorDto {
+                facility: get_facility("reactor"),
+                status: data.reactor,
+            },
+            water_treatment: get_facility("water-treatment"),
             infirmary: data.infirmary,
             workshop: data.workshop,
             horticulture: data.horticulture,
-            air_recycling: data.air_recycling,
+            air_recycling: get_facility("air-recycling"),
             cafeteria: data.cafeteria,
+            security: data.security,
+            power: data.power,
+            shielded_storage: data.shielded_storage,
+            storage: data.storage,
+            rooms: data.rooms,
+            construction: data.construction,
+            facilities: data.facilities,
         }
     }
 }
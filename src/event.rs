@@ -0,0 +1,230 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    data::{get_item_type, EventCondition, EventEffect, LocationEvent},
+    db::inhabitants::Inhabitant,
+    util::skill_roll,
+};
+
+#[derive(Default)]
+pub struct EventOutcome {
+    pub recruits: i32,
+}
+
+/// Resolves a location event for a team, appending the outcome to the report.
+pub fn resolve(
+    event: &LocationEvent,
+    team: &mut [Inhabitant],
+    found: &mut HashMap<String, i32>,
+    report_body: &mut String,
+) -> EventOutcome {
+    let mut outcome = EventOutcome::default();
+    report_body.push_str(&format!("{}\n", event.text));
+    let success = event
+        .conditions
+        .iter()
+        .all(|condition| check_condition(condition, team, found));
+    let (text, effects) = if success {
+        (&event.success_text, &event.success)
+    } else {
+        (&event.failure_text, &event.failure)
+    };
+    if let Some(text) = text {
+        report_body.push_str(&format!("{}\n", text));
+    }
+    for effect in effects {
+        apply_effect(effect, team, found, &mut outcome, report_body);
+    }
+    outcome
+}
+
+fn check_condition(
+    condition: &EventCondition,
+    team: &[Inhabitant],
+    found: &HashMap<String, i32>,
+) -> bool {
+    match condition {
+        EventCondition::Skill { skill, level } => {
+            team.iter().any(|m| m.get_skill_level(*skill) >= *level)
+        }
+        EventCondition::SkillRoll { skill, chance } => {
+            let best = team
+                .iter()
+                .map(|m| m.get_skill_level(*skill))
+                .max()
+                .unwrap_or(0);
+            skill_roll(*chance, best)
+        }
+        EventCondition::Item { item_type } => {
+            found.get(item_type).cloned().unwrap_or(0) > 0
+                || team.iter().any(|m| {
+                    [
+                        &m.data.weapon_type,
+                        &m.data.pack_type,
+                        &m.data.outfit_type,
+                        &m.data.armor_type,
+                    ]
+                    .iter()
+                    .any(|carried| carried.as_ref() == Some(item_type))
+                })
+        }
+    }
+}
+
+fn apply_effect(
+    effect: &EventEffect,
+    team: &mut [Inhabitant],
+    found: &mut HashMap<String, i32>,
+    outcome: &mut EventOutcome,
+    report_body: &mut String,
+) {
+    match effect {
+        EventEffect::Loot {
+            item_type,
+            min,
+            max,
+        } => {
+            let quantity = rand::thread_rng().gen_range(*min..*max + 1);
+            if quantity < 1 {
+                return;
+            }
+            let item = get_item_type(item_type);
+            if quantity == 1 {
+                report_body.push_str(&format!("Found {}\n", item.name));
+            } else {
+                report_body.push_str(&format!("Found {} ({})\n", item.name_plural, quantity));
+            }
+            *found.entry(item_type.clone()).or_insert(0) += quantity;
+        }
+        EventEffect::Wound { damage } => {
+            if let Some(member) = team.choose_mut(&mut rand::thread_rng()) {
+                member.data.health -= damage;
+                member.data.wounded = true;
+                member.changed = true;
+                report_body.push_str(&format!("{} was wounded\n", member.name));
+            }
+        }
+        EventEffect::Xp { skill, xp } => {
+            for member in team.iter_mut() {
+                if member.add_xp(*skill, *xp) {
+                    report_body.push_str(&format!("{} learned something new\n", member.name));
+                }
+            }
+        }
+        EventEffect::Recruit => {
+            outcome.recruits += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::LOCATION_TYPES,
+        db::inhabitants::{test_inhabitant, InhabitantData, SkillType},
+    };
+
+    fn create_team() -> Vec<Inhabitant> {
+        vec![test_inhabitant(
+            1,
+            InhabitantData {
+                health: 100,
+                ..Default::default()
+            },
+        )]
+    }
+
+    fn create_event(conditions: Vec<EventCondition>) -> LocationEvent {
+        LocationEvent {
+            chance: 1.0,
+            text: "A locked door".to_owned(),
+            conditions,
+            success_text: Some("The door opened".to_owned()),
+            success: vec![
+                EventEffect::Xp {
+                    skill: SkillType::Repair,
+                    xp: 100,
+                },
+                EventEffect::Recruit,
+            ],
+            failure_text: Some("The door was trapped".to_owned()),
+            failure: vec![EventEffect::Wound { damage: 10 }],
+        }
+    }
+
+    #[test]
+    fn failure_wounds_a_member() {
+        let event = create_event(vec![EventCondition::Skill {
+            skill: SkillType::Repair,
+            level: 1,
+        }]);
+        let mut team = create_team();
+        let mut report = String::new();
+        let outcome = resolve(&event, &mut team, &mut HashMap::new(), &mut report);
+        assert_eq!(0, outcome.recruits);
+        assert!(team[0].data.wounded);
+        assert!(team[0].changed);
+        assert_eq!(90, team[0].data.health);
+        assert_eq!(
+            "A locked door\nThe door was trapped\nInhabitant 1 was wounded\n",
+            report
+        );
+    }
+
+    #[test]
+    fn success_gives_xp_and_recruits() {
+        let event = create_event(vec![EventCondition::Skill {
+            skill: SkillType::Repair,
+            level: 0,
+        }]);
+        let mut team = create_team();
+        let mut report = String::new();
+        let outcome = resolve(&event, &mut team, &mut HashMap::new(), &mut report);
+        assert_eq!(1, outcome.recruits);
+        assert!(!team[0].data.wounded);
+        assert_eq!(1, team[0].get_skill_level(SkillType::Repair));
+        assert_eq!(
+            "A locked door\nThe door opened\nInhabitant 1 learned something new\n",
+            report
+        );
+        // The skill gained makes the next attempt succeed
+        let event = create_event(vec![EventCondition::Skill {
+            skill: SkillType::Repair,
+            level: 1,
+        }]);
+        let outcome = resolve(&event, &mut team, &mut HashMap::new(), &mut String::new());
+        assert_eq!(1, outcome.recruits);
+    }
+
+    #[test]
+    fn item_condition_checks_found_loot() {
+        let event = LOCATION_TYPES
+            .values()
+            .flat_map(|l| l.events.iter())
+            .find(|e| {
+                e.conditions
+                    .iter()
+                    .any(|c| matches!(c, EventCondition::Item { .. }))
+            })
+            .expect("No event with item condition");
+        let mut found = HashMap::new();
+        for condition in &event.conditions {
+            if let EventCondition::Item { item_type } = condition {
+                found.insert(item_type.clone(), 1);
+            }
+        }
+        assert!(event
+            .conditions
+            .iter()
+            .filter(|c| matches!(c, EventCondition::Item { .. }))
+            .all(|c| check_condition(c, &[], &found)));
+    }
+}
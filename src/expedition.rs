@@ -6,32 +6,42 @@
 use std::collections::HashMap;
 
 use actix::Addr;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use itertools::Itertools;
 use rand::Rng;
 use sqlx::PgPool;
 
 use crate::{
     battle,
-    broadcaster::{Broadcaster, BunkerMessage, Message},
-    data::{ITEM_TYPES, LOCATION_TYPES},
+    broadcaster::{Broadcaster, BunkerMessage, ExpeditionEvent, Message},
+    data::{get_item_type, ITEM_TYPES, LOCATION_TYPES},
     db::{
-        bunkers::Bunker,
-        expeditions,
-        inhabitants::{self, get_age, SkillType},
-        items, locations, messages,
+        battles,
+        bunkers::{self, Bunker},
+        expeditions::{self, Expedition, Mission, Tactic},
+        inhabitants::{self, get_age, Inhabitant, SkillType},
+        items, locations, messages, recruits, sectors,
         worlds::{self, WorldTime},
     },
-    error,
-    util::{self, get_sector_name, roll_dice, skill_roll},
+    error, event, hazard, location, pvp, recruit, terrain,
+    util::{get_sector_name, roll_dice, skill_roll},
 };
 
+const BASE_CARRYING_CAPACITY: f64 = 20.0;
+const BASE_CARRYING_VOLUME: f64 = 25.0;
+
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TeamMember {
     inhabitant_id: i32,
     weapon_type: Option<String>,
     ammo: i32,
+    #[serde(default)]
+    pack_type: Option<String>,
+    #[serde(default)]
+    outfit_type: Option<String>,
+    #[serde(default)]
+    armor_type: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -41,6 +51,10 @@ pub struct ExpeditionRequest {
     zone_y: i32,
     location_id: Option<i32>,
     team: Vec<TeamMember>,
+    #[serde(default)]
+    tactic: Tactic,
+    #[serde(default)]
+    mission: Mission,
 }
 
 pub async fn create(
@@ -57,10 +71,36 @@ pub async fn create(
     if request.zone_x < 0 || request.zone_x >= 26 || request.zone_y < 0 || request.zone_y >= 26 {
         Err(error::client_error("INVALID_ZONE"))?;
     }
-    let distance = util::get_distance(
-        (bunker.x, bunker.y),
-        (request.zone_x * 100 + 50, request.zone_y * 100 + 50),
-    );
+    let target_bunker_id = match request.location_id {
+        Some(location_id) => {
+            let location = locations::get_location(pool, location_id).await?;
+            if location.data.depleted {
+                Err(error::client_error("LOCATION_DEPLETED"))?;
+            }
+            location.data.bunker_id
+        }
+        None => None,
+    };
+    match (request.mission, target_bunker_id) {
+        (Mission::Scavenge, None) => (),
+        (Mission::Scavenge, Some(_)) | (_, None) => Err(error::client_error("INVALID_MISSION"))?,
+        (_, Some(target_bunker_id)) => {
+            if !worlds::get_world(pool, world_id).await?.pvp {
+                Err(error::client_error("PVP_DISABLED"))?;
+            }
+            if target_bunker_id == bunker.id {
+                Err(error::client_error("INVALID_TARGET"))?;
+            }
+        }
+    }
+    let route = terrain::get_world_map(pool, world_id)
+        .await?
+        .find_route(
+            (bunker.x, bunker.y),
+            (request.zone_x * 100 + 50, request.zone_y * 100 + 50),
+        )
+        .ok_or_else(|| error::client_error("NO_ROUTE"))?;
+    let distance = route.distance;
     let world_time = worlds::get_world_time(&pool, world_id).await?;
     let inhabitant_ids = request.team.iter().map(|m| m.inhabitant_id).collect_vec();
     let mut inhabitants =
@@ -76,6 +116,27 @@ pub async fn create(
                 item_types.push(ammo_type_id.clone());
             }
         }
+        if let Some(pack_type_id) = &member.pack_type {
+            ITEM_TYPES
+                .get(pack_type_id)
+                .filter(|p| p.carrying_capacity > 0.0 || p.carrying_volume > 0.0)
+                .ok_or_else(|| error::client_error("INVALID_PACK_TYPE"))?;
+            item_types.push(pack_type_id.clone());
+        }
+        if let Some(outfit_type_id) = &member.outfit_type {
+            ITEM_TYPES
+                .get(outfit_type_id)
+                .filter(|o| o.radiation_protection > 0)
+                .ok_or_else(|| error::client_error("INVALID_OUTFIT_TYPE"))?;
+            item_types.push(outfit_type_id.clone());
+        }
+        if let Some(armor_type_id) = &member.armor_type {
+            ITEM_TYPES
+                .get(armor_type_id)
+                .filter(|a| a.armor > 0)
+                .ok_or_else(|| error::client_error("INVALID_ARMOR_TYPE"))?;
+            item_types.push(armor_type_id.clone());
+        }
     }
     let mut items: HashMap<String, (i32, i32)> =
         items::get_items_by_id(pool, bunker.id, item_types)
@@ -122,11 +183,40 @@ pub async fn create(
             inhabintant.data.weapon_type = None;
             inhabintant.data.ammo = 0;
         }
+        if let Some(pack_type_id) = &member.pack_type {
+            let item = items
+                .get_mut(pack_type_id)
+                .filter(|i| i.0 > 0)
+                .ok_or_else(|| error::client_error("PACK_TYPE_MISSING"))?;
+            item.0 -= 1;
+            item.1 += 1;
+        }
+        inhabintant.data.pack_type = member.pack_type.clone();
+        if let Some(outfit_type_id) = &member.outfit_type {
+            let item = items
+                .get_mut(outfit_type_id)
+                .filter(|i| i.0 > 0)
+                .ok_or_else(|| error::client_error("OUTFIT_TYPE_MISSING"))?;
+            item.0 -= 1;
+            item.1 += 1;
+        }
+        inhabintant.data.outfit_type = member.outfit_type.clone();
+        if let Some(armor_type_id) = &member.armor_type {
+            let item = items
+                .get_mut(armor_type_id)
+                .filter(|i| i.0 > 0)
+                .ok_or_else(|| error::client_error("ARMOR_TYPE_MISSING"))?;
+            item.0 -= 1;
+            item.1 += 1;
+        }
+        inhabintant.data.armor_type = member.armor_type.clone();
         inhabintant.data.sleeping = false;
     }
+    let hazard = sectors::get_sector_hazard(pool, world_id, request.zone_x, request.zone_y).await?;
     let speed = 5 * 1000 / 60;
-    let duration =
-        Duration::minutes((10 + 2 * distance / speed) as i64) / world_time.time_acceleration;
+    let minutes = (10 + 2 * route.travel_distance / speed) as f64
+        * world_time.data.weather.travel_time_multiplier();
+    let duration = Duration::minutes(minutes as i64) / world_time.time_acceleration;
     let eta = Utc::now() + duration;
     let new_expedition = expeditions::NewExpedition {
         bunker_id: bunker.id,
@@ -134,7 +224,20 @@ pub async fn create(
         zone_x: request.zone_x,
         zone_y: request.zone_y,
         eta,
-        data: expeditions::ExpeditionData { distance },
+        data: expeditions::ExpeditionData {
+            distance,
+            arrived: false,
+            recalled: false,
+            retreat: false,
+            report: String::new(),
+            loot: HashMap::new(),
+            route: route.waypoints,
+            exposure: route.exposure,
+            hazard,
+            tactic: request.tactic,
+            mission: request.mission,
+            recruits: 0,
+        },
     };
     let mut tx = pool.begin().await?;
     let expedition_id = expeditions::create_expedition(&pool, &new_expedition).await?;
@@ -161,165 +264,546 @@ pub async fn create(
             .execute(&mut tx)
             .await?;
     }
+    bunkers::add_expedition_departure_query(bunker.id, Utc::now())
+        .execute(&mut tx)
+        .await?;
     tx.commit().await?;
     items::remove_empty_items(pool, bunker.id).await?;
     Ok(())
 }
 
-pub async fn handle_finished_expeditions(
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecallRequest {
+    expedition_id: i32,
+}
+
+pub async fn recall(
+    pool: &PgPool,
+    bunker: &Bunker,
+    request: &RecallRequest,
+) -> Result<(), error::Error> {
+    // Locked so that the team can't turn around while the recall is being written
+    let mut tx = pool.begin().await?;
+    let mut expedition = expeditions::get_expedition_for_update(&mut tx, request.expedition_id)
+        .await?
+        .filter(|expedition| expedition.bunker_id == bunker.id)
+        .ok_or_else(|| error::client_error("EXPEDITION_NOT_FOUND"))?;
+    if expedition.data.recalled {
+        Err(error::client_error("ALREADY_RECALLED"))?;
+    }
+    if expedition.data.arrived {
+        Err(error::client_error("ALREADY_RETURNING"))?;
+    }
+    let now = Utc::now();
+    let (distance, eta) = get_recall_return(
+        expedition.created,
+        expedition.eta,
+        expedition.data.distance,
+        now,
+    )
+    .ok_or_else(|| error::client_error("ALREADY_RETURNING"))?;
+    expedition.data.distance = distance;
+    expedition.data.recalled = true;
+    expedition.eta = eta;
+    expeditions::update_expedition_query(&expedition)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Distance covered and new ETA of an expedition recalled at `now`, or none if it has already
+/// turned around. The team turns around on the spot, so the way back takes as long as the way
+/// out did.
+fn get_recall_return(
+    created: DateTime<Utc>,
+    eta: DateTime<Utc>,
+    distance: i32,
+    now: DateTime<Utc>,
+) -> Option<(i32, DateTime<Utc>)> {
+    let elapsed = now - created;
+    let outbound = (eta - created) / 2;
+    if elapsed >= outbound {
+        return None;
+    }
+    let covered = distance as i64 * elapsed.num_seconds() / outbound.num_seconds().max(1);
+    Some((covered as i32, now + elapsed))
+}
+
+fn get_turnaround_time(expedition: &Expedition) -> DateTime<Utc> {
+    expedition.created + (expedition.eta - expedition.created) / 2
+}
+
+pub async fn handle_active_expeditions(
     pool: &PgPool,
     world: &WorldTime,
     broadcaster: &Addr<Broadcaster>,
 ) -> Result<(), error::Error> {
-    let expeditions = expeditions::get_finished_expeditions(pool, world.id).await?;
+    let expeditions = expeditions::get_outbound_expeditions(pool, world.id).await?;
+    let now = Utc::now();
     for expedition in expeditions {
-        let sector_name = get_sector_name((expedition.zone_x, expedition.zone_y));
-        let mut report_body = "".to_string();
+        if now < get_turnaround_time(&expedition) {
+            continue;
+        }
+        // Reloaded with the row locked in case the expedition was recalled in the meantime
+        let mut tx = pool.begin().await?;
+        let mut expedition =
+            match expeditions::get_expedition_for_update(&mut tx, expedition.id).await? {
+                Some(expedition)
+                    if !expedition.data.arrived && now >= get_turnaround_time(&expedition) =>
+                {
+                    expedition
+                }
+                _ => continue,
+            };
         let mut team = inhabitants::get_by_expedition(pool, expedition.id).await?;
-        let encounter_chances = expedition.data.distance / 2000 * 2;
-        let mut retreat = false;
-        if encounter_chances > 0 {
-            if roll_dice(0.2, encounter_chances) {
-                if !battle::encounter(&mut team, &mut report_body, encounter_chances)? {
-                    retreat = true;
+        handle_turnaround(pool, world, &mut expedition, &mut team, broadcaster).await?;
+        for member in &team {
+            inhabitants::update_inhabitant_data(pool, member).await?;
+        }
+        expeditions::update_expedition_query(&expedition)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+async fn handle_turnaround(
+    pool: &PgPool,
+    world: &WorldTime,
+    expedition: &mut Expedition,
+    team: &mut Vec<Inhabitant>,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    let sector_name = get_sector_name((expedition.zone_x, expedition.zone_y));
+    let mut report_body = String::new();
+    let encounter_chances = expedition.data.distance / 2000 * 2;
+    let mut encountered = false;
+    let mut retreat = false;
+    expedition.data.hazard =
+        sectors::get_sector_hazard(pool, world.id, expedition.zone_x, expedition.zone_y).await?;
+    // Marauders tend to stay clear of the most contaminated sectors
+    let encounter_chance = 0.2
+        * (1.0 - expedition.data.hazard as f64 / 200.0)
+        * world.data.weather.encounter_multiplier();
+    let mut found: HashMap<String, i32> = HashMap::new();
+    if encounter_chances > 0 && roll_dice(encounter_chance, encounter_chances) {
+        encountered = true;
+        let map = terrain::get_world_map(pool, world.id).await?;
+        let terrain = map.get_terrain(expedition.zone_x * 100 + 50, expedition.zone_y * 100 + 50);
+        let log = battle::encounter(team, encounter_chances, expedition.data.tactic, &terrain.id)?;
+        report_body.push_str(&log.to_report());
+        retreat = log.is_retreat();
+        battles::create_battle(pool, expedition.bunker_id, Some(expedition.id), &log).await?;
+        for (item_type_id, quantity) in log.loot {
+            let item_type = get_item_type(&item_type_id);
+            if quantity == 1 {
+                report_body.push_str(&format!("Recovered {}\n", &item_type.name));
+            } else {
+                report_body.push_str(&format!(
+                    "Recovered {} ({})\n",
+                    &item_type.name_plural, quantity
+                ));
+            }
+            found.insert(item_type_id, quantity);
+        }
+        let mut first_aid_applied: Vec<(i32, i32)> = vec![];
+        for wounded in team.iter() {
+            if !wounded.data.wounded && !wounded.data.bleeding {
+                continue;
+            }
+            for member in team.iter() {
+                if member.id == wounded.id {
+                    continue;
                 }
-                let mut first_aid_applied: Vec<(i32, i32)> = vec![];
-                for wounded in &team {
-                    if !wounded.data.wounded && !wounded.data.bleeding {
-                        continue;
-                    }
-                    for member in &team {
-                        if member.id == wounded.id {
-                            continue;
-                        }
-                        let first_aid = member.get_skill_level(SkillType::FirstAid);
-                        let medicine = member.get_skill_level(SkillType::Medicine);
-                        if skill_roll(0.1, first_aid + medicine) {
-                            first_aid_applied.push((wounded.id, member.id));
-                            report_body.push_str(&format!(
-                                "{} successfully applied first aid to {}\n",
-                                member.name, wounded.name
-                            ));
-                        }
-                    }
+                let first_aid = member.get_skill_level(SkillType::FirstAid);
+                let medicine = member.get_skill_level(SkillType::Medicine);
+                if skill_roll(0.1, first_aid + medicine) {
+                    first_aid_applied.push((wounded.id, member.id));
+                    report_body.push_str(&format!(
+                        "{} successfully applied first aid to {}\n",
+                        member.name, wounded.name
+                    ));
                 }
-                for member in &mut team {
-                    for (recipient_id, other_id) in &first_aid_applied {
-                        if *recipient_id == member.id {
-                            member.data.bleeding = false;
-                        } else if *other_id == member.id {
-                            member.add_xp(SkillType::FirstAid, 50);
-                        }
-                    }
-                    if member.data.bleeding && !retreat {
-                        report_body.push_str(&format!("Returned with wounded\n"));
-                        retreat = true;
-                    }
+            }
+        }
+        for member in team.iter_mut() {
+            for (recipient_id, other_id) in &first_aid_applied {
+                if *recipient_id == member.id {
+                    member.data.bleeding = false;
+                } else if *other_id == member.id {
+                    member.add_xp(SkillType::FirstAid, 50);
                 }
             }
+            if member.data.bleeding && !retreat {
+                report_body.push_str(&format!("Returned with wounded\n"));
+                retreat = true;
+            }
         }
-        if !retreat {
-            if let Some(location_id) = expedition.location_id {
-                let mut location = locations::get_location(pool, location_id).await?;
-                report_body.push_str(&format!(
-                    "Successfully searched {} in sector {}\n",
-                    location.name, sector_name
-                ));
-                let mut base_chance = if location.data.searches > 0 {
-                    1.0 / location.data.searches as f64
+    }
+    if !retreat && !expedition.data.recalled {
+        if let Some(location_id) = expedition.location_id {
+            match expedition.data.mission {
+                Mission::Scavenge => {
+                    expedition.data.recruits += scavenge(
+                        pool,
+                        location_id,
+                        &sector_name,
+                        team,
+                        &mut found,
+                        &mut report_body,
+                    )
+                    .await?
+                }
+                Mission::Scout => {
+                    pvp::scout(pool, expedition, location_id, team, &mut report_body).await?
+                }
+                Mission::Raid => {
+                    retreat = pvp::raid(
+                        pool,
+                        world,
+                        expedition,
+                        location_id,
+                        team,
+                        &mut found,
+                        &mut report_body,
+                    )
+                    .await?
+                }
+            }
+        }
+    }
+    carry_loot(expedition, team, &found, &mut report_body);
+    let wounded = team.iter().any(|m| m.data.wounded || m.data.bleeding);
+    expedition.data.arrived = true;
+    expedition.data.retreat = retreat;
+    expedition.data.report.push_str(&report_body);
+    let mut events = vec![];
+    if !expedition.data.recalled {
+        events.push(ExpeditionEvent::Halfway);
+    }
+    if encountered {
+        events.push(ExpeditionEvent::Encounter);
+    }
+    if wounded {
+        events.push(ExpeditionEvent::Wounded);
+    }
+    for event in events {
+        broadcaster.do_send(BunkerMessage {
+            bunker_id: expedition.bunker_id,
+            message: Message::ExpeditionEvent {
+                expedition_id: expedition.id,
+                event,
+            },
+        });
+    }
+    let status = if expedition.data.recalled {
+        format!("The team has turned back and is returning to the bunker.\n")
+    } else if retreat {
+        format!("The team is retreating from sector {}.\n", sector_name)
+    } else {
+        format!("The team has reached sector {}.\n", sector_name)
+    };
+    messages::create_system_message(
+        pool,
+        &messages::NewSystemMessage {
+            receiver_bunker_id: expedition.bunker_id,
+            sender_name: format!("Mission team"),
+            subject: format!("Mission update (Sector {})", sector_name),
+            body: status + &report_body,
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+async fn scavenge(
+    pool: &PgPool,
+    location_id: i32,
+    sector_name: &str,
+    team: &mut Vec<Inhabitant>,
+    found: &mut HashMap<String, i32>,
+    report_body: &mut String,
+) -> Result<i32, error::Error> {
+    let mut location = locations::get_location(pool, location_id).await?;
+    if location.data.depleted {
+        report_body.push_str(&format!(
+            "Nothing was left to find at {} in sector {}\n",
+            location.name, sector_name
+        ));
+        return Ok(0);
+    }
+    report_body.push_str(&format!(
+        "Successfully searched {} in sector {}\n",
+        location.name, sector_name
+    ));
+    let mut base_chance = if location.data.searches > 0 {
+        1.0 / location.data.searches as f64
+    } else {
+        1.0
+    };
+    let location_type = LOCATION_TYPES
+        .get(&location.data.location_type)
+        .ok_or_else(|| error::internal_error("Unknown location type"))?;
+    for member in team.iter_mut() {
+        let scavenging_level = member.get_skill_level(SkillType::Scavenging);
+        for (item_type_id, entry) in &location_type.loot {
+            if skill_roll(base_chance * entry.chance, scavenging_level) {
+                let item_type = ITEM_TYPES
+                    .get(item_type_id)
+                    .ok_or_else(|| error::internal_error("Item type not found"))?;
+                let quantity = rand::thread_rng().gen_range(entry.min..entry.max + 1);
+                if quantity == 1 {
+                    report_body.push_str(&format!("Found {}\n", &item_type.name));
                 } else {
-                    1.0
-                };
-                let location_type = LOCATION_TYPES
-                    .get(&location.data.location_type)
-                    .ok_or_else(|| error::internal_error("Unknown location type"))?;
-                for member in &mut team {
-                    let scavenging_level = member.get_skill_level(SkillType::Scavenging);
-                    for (item_type_id, entry) in &location_type.loot {
-                        if skill_roll(base_chance * entry.chance, scavenging_level) {
-                            let item_type = ITEM_TYPES
-                                .get(item_type_id)
-                                .ok_or_else(|| error::internal_error("Item type not found"))?;
-                            let quantity = rand::thread_rng().gen_range(entry.min..entry.max + 1);
-                            if quantity == 1 {
-                                report_body.push_str(&format!("Found {}\n", &item_type.name));
-                            } else {
-                                report_body.push_str(&format!(
-                                    "Found {} ({})\n",
-                                    &item_type.name_plural, quantity
-                                ));
-                            }
-                            items::add_item(pool, expedition.bunker_id, item_type_id, quantity)
-                                .await?;
-                            if member.add_xp(SkillType::Scavenging, 60) {
-                                report_body.push_str(&format!(
-                                    "{} got better at scavenging\n",
-                                    member.name
-                                ));
-                            }
-                        }
-                    }
-                    base_chance /= 2.0;
+                    report_body.push_str(&format!(
+                        "Found {} ({})\n",
+                        &item_type.name_plural, quantity
+                    ));
                 }
-                location.data.searches += 1;
-                locations::update_location(pool, &location).await?;
-            } else {
-                let locations = locations::get_undiscovered_locations(
-                    pool,
-                    expedition.bunker_id,
-                    expedition.zone_x,
-                    expedition.zone_y,
-                )
-                .await?;
-                let mut discovered = 0;
-                for location in &locations {
-                    for member in &team {
-                        let exploration_level = member.get_skill_level(SkillType::Exploration);
-                        if skill_roll(0.25, exploration_level) {
-                            discovered += 1;
-                            report_body.push_str(&format!(
-                                "Location discovered in sector {}: {}\n",
-                                sector_name, location.name
-                            ));
-                            locations::add_bunker_location(pool, expedition.bunker_id, location.id)
-                                .await?;
-                            break;
-                        }
+                *found.entry(item_type_id.clone()).or_insert(0) += quantity;
+                if member.add_xp(SkillType::Scavenging, 60) {
+                    report_body.push_str(&format!("{} got better at scavenging\n", member.name));
+                }
+            }
+        }
+        base_chance /= 2.0;
+    }
+    let mut recruits = 0;
+    for location_event in &location_type.events {
+        if roll_dice(location_event.chance, 1) {
+            recruits += event::resolve(location_event, team, found, report_body).recruits;
+        }
+    }
+    if location::mark_searched(&mut location.data, location_type) {
+        report_body.push_str(&format!("{} has been picked clean\n", location.name));
+    }
+    locations::update_location(pool, &location).await?;
+    Ok(recruits)
+}
+
+fn carry_loot(
+    expedition: &mut Expedition,
+    team: &[Inhabitant],
+    found: &HashMap<String, i32>,
+    report_body: &mut String,
+) {
+    let (max_weight, max_volume) = get_carrying_capacity(team);
+    let (carried, left_behind) = pack_loot(found, max_weight, max_volume);
+    for (item_type_id, quantity) in &left_behind {
+        let item_type = get_item_type(item_type_id);
+        if *quantity == 1 {
+            report_body.push_str(&format!("Left behind {}\n", &item_type.name));
+        } else {
+            report_body.push_str(&format!(
+                "Left behind {} ({})\n",
+                &item_type.name_plural, quantity
+            ));
+        }
+    }
+    let load = get_load(&carried).0;
+    if load > 0.0 && max_weight > 0.0 {
+        // A fully loaded team walks back at two thirds of its normal speed
+        let remaining = expedition.eta - Utc::now();
+        if remaining > Duration::zero() {
+            let factor = 1.0 + 0.5 * (load / max_weight).min(1.0);
+            expedition.eta =
+                Utc::now() + Duration::seconds((remaining.num_seconds() as f64 * factor) as i64);
+        }
+    }
+    expedition.data.loot = carried;
+}
+
+/// Total weight (kg) and volume (l) the team can carry back.
+pub fn get_carrying_capacity(team: &[Inhabitant]) -> (f64, f64) {
+    team.iter().filter(|member| member.data.health > 0).fold(
+        (0.0, 0.0),
+        |(weight, volume), member| {
+            let pack = member
+                .data
+                .pack_type
+                .as_ref()
+                .and_then(|pack_type| ITEM_TYPES.get(pack_type));
+            let strength = member.data.health.max(0) as f64 / 100.0;
+            (
+                weight
+                    + (BASE_CARRYING_CAPACITY + pack.map(|p| p.carrying_capacity).unwrap_or(0.0))
+                        * strength,
+                volume + BASE_CARRYING_VOLUME + pack.map(|p| p.carrying_volume).unwrap_or(0.0),
+            )
+        },
+    )
+}
+
+fn get_load(loot: &HashMap<String, i32>) -> (f64, f64) {
+    loot.iter()
+        .fold((0.0, 0.0), |(weight, volume), (item_type_id, quantity)| {
+            let item_type = get_item_type(item_type_id);
+            (
+                weight + item_type.weight * *quantity as f64,
+                volume + item_type.volume * *quantity as f64,
+            )
+        })
+}
+
+/// Splits found loot into what the team carries back and what is left behind, picking the most
+/// valuable items per kilogram first.
+pub fn pack_loot(
+    found: &HashMap<String, i32>,
+    max_weight: f64,
+    max_volume: f64,
+) -> (HashMap<String, i32>, HashMap<String, i32>) {
+    let mut carried = HashMap::new();
+    let mut left_behind = HashMap::new();
+    let mut weight = 0.0;
+    let mut volume = 0.0;
+    let sorted = found
+        .iter()
+        .map(|(item_type_id, quantity)| (get_item_type(item_type_id), *quantity))
+        .sorted_by(|(a, _), (b, _)| {
+            let a_density = a.value as f64 / a.weight.max(0.01);
+            let b_density = b.value as f64 / b.weight.max(0.01);
+            b_density
+                .partial_cmp(&a_density)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+    for (item_type, quantity) in sorted {
+        let mut taken = 0;
+        while taken < quantity
+            && weight + item_type.weight <= max_weight
+            && volume + item_type.volume <= max_volume
+        {
+            weight += item_type.weight;
+            volume += item_type.volume;
+            taken += 1;
+        }
+        if taken > 0 {
+            carried.insert(item_type.id.clone(), taken);
+        }
+        if taken < quantity {
+            left_behind.insert(item_type.id.clone(), quantity - taken);
+        }
+    }
+    (carried, left_behind)
+}
+
+pub async fn handle_finished_expeditions(
+    pool: &PgPool,
+    world: &WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    let expeditions = expeditions::get_finished_expeditions(pool, world.id).await?;
+    for mut expedition in expeditions {
+        let sector_name = get_sector_name((expedition.zone_x, expedition.zone_y));
+        let mut team = inhabitants::get_by_expedition(pool, expedition.id).await?;
+        if !expedition.data.arrived {
+            handle_turnaround(pool, world, &mut expedition, &mut team, broadcaster).await?;
+        }
+        let mut report_body = std::mem::take(&mut expedition.data.report);
+        let retreat = expedition.data.retreat;
+        if expedition.data.recalled {
+            report_body.push_str(&format!(
+                "Recalled before reaching sector {}\n",
+                sector_name
+            ));
+        } else if !retreat && expedition.location_id.is_none() {
+            let locations = locations::get_undiscovered_locations(
+                pool,
+                expedition.bunker_id,
+                expedition.zone_x,
+                expedition.zone_y,
+            )
+            .await?;
+            let mut discovered = 0;
+            for location in &locations {
+                for member in &team {
+                    let exploration_level = member.get_skill_level(SkillType::Exploration);
+                    if skill_roll(0.25, exploration_level) {
+                        discovered += 1;
+                        report_body.push_str(&format!(
+                            "Location discovered in sector {}: {}\n",
+                            sector_name, location.name
+                        ));
+                        locations::add_bunker_location(pool, expedition.bunker_id, location.id)
+                            .await?;
+                        break;
                     }
                 }
-                if discovered == 0 {
-                    report_body.push_str("No new locations discovered");
-                } else {
-                    let xp = discovered * 40;
-                    for member in &mut team {
-                        if member.add_xp(SkillType::Exploration, xp) {
-                            report_body
-                                .push_str(&format!("{} got better at exploration\n", member.name));
-                        }
+            }
+            if discovered == 0 {
+                report_body.push_str("No new locations discovered");
+            } else {
+                let xp = discovered * 40;
+                for member in &mut team {
+                    if member.add_xp(SkillType::Exploration, xp) {
+                        report_body
+                            .push_str(&format!("{} got better at exploration\n", member.name));
                     }
                 }
-                if discovered >= locations.len() as i32 {
-                    for member in &team {
-                        let exploration_level = member.get_skill_level(SkillType::Exploration);
-                        if skill_roll(0.25, exploration_level) {
-                            locations::add_bunker_sector(
-                                pool,
-                                expedition.bunker_id,
-                                expedition.zone_x,
-                                expedition.zone_y,
-                            )
-                            .await?;
-                            break;
-                        }
+            }
+            if discovered >= locations.len() as i32 {
+                for member in &team {
+                    let exploration_level = member.get_skill_level(SkillType::Exploration);
+                    if skill_roll(0.25, exploration_level) {
+                        locations::add_bunker_sector(
+                            pool,
+                            expedition.bunker_id,
+                            expedition.zone_x,
+                            expedition.zone_y,
+                        )
+                        .await?;
+                        break;
                     }
                 }
             }
         }
-        let exposure = 1
-            + ((Utc::now() - expedition.created) * world.time_acceleration).num_hours() as i32 * 3;
+        if !expedition.data.recalled {
+            locations::set_sector_visited(
+                pool,
+                expedition.bunker_id,
+                expedition.zone_x,
+                expedition.zone_y,
+            )
+            .await?;
+        }
+        for (item_type_id, quantity) in &expedition.data.loot {
+            items::add_item(pool, expedition.bunker_id, item_type_id, *quantity).await?;
+        }
+        for _ in 0..expedition.data.recruits {
+            let survivor = recruit::generate_survivor(world.now());
+            recruits::create_recruit(pool, expedition.bunker_id, &survivor).await?;
+            report_body.push_str(&format!(
+                "{} came back with the team and is waiting outside the bunker\n",
+                survivor.name
+            ));
+        }
+        let hours = ((Utc::now() - expedition.created) * world.time_acceleration).num_hours();
+        let exposure = (1 + hours as i32 * 3) as f64
+            * expedition.data.exposure
+            * hazard::get_exposure_multiplier(expedition.data.hazard)
+            * world.data.weather.exposure_multiplier();
         for member in &mut team {
-            member.data.surface_exposure += exposure;
+            let protection = member
+                .data
+                .outfit_type
+                .as_ref()
+                .map(|outfit_type| get_item_type(outfit_type).radiation_protection)
+                .unwrap_or(0);
+            member.data.surface_exposure +=
+                (exposure * (100 - protection).max(0) as f64 / 100.0).round() as i32;
             inhabitants::update_inhabitant_data(pool, &member).await?;
+            if let Some(pack_type_id) = &member.data.pack_type {
+                items::add_item(pool, expedition.bunker_id, &pack_type_id, 1).await?;
+            }
+            if let Some(outfit_type_id) = &member.data.outfit_type {
+                items::add_item(pool, expedition.bunker_id, &outfit_type_id, 1).await?;
+            }
+            if let Some(armor_type_id) = &member.data.armor_type {
+                items::add_item(pool, expedition.bunker_id, armor_type_id, 1).await?;
+            }
             if let Some(weapon_type_id) = &member.data.weapon_type {
                 items::add_item(pool, expedition.bunker_id, &weapon_type_id, 1).await?;
                 let weapon_type = ITEM_TYPES
@@ -356,3 +840,47 @@ pub async fn handle_finished_expeditions(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recalled_team_returns_the_way_it_came() {
+        let created = Utc::now();
+        let eta = created + Duration::hours(20);
+        let now = created + Duration::hours(4);
+        assert_eq!(
+            Some((40, created + Duration::hours(8))),
+            get_recall_return(created, eta, 100, now)
+        );
+        assert_eq!(
+            None,
+            get_recall_return(created, eta, 100, created + Duration::hours(10))
+        );
+    }
+
+    #[test]
+    fn can_pack_loot() {
+        let found = HashMap::from([
+            ("medicine".to_owned(), 2),
+            ("scrap-metal".to_owned(), 10),
+            ("wood".to_owned(), 5),
+        ]);
+        let (carried, left_behind) = pack_loot(&found, 5.0, 100.0);
+        assert_eq!(Some(&2), carried.get("medicine"));
+        assert_eq!(Some(&4), carried.get("scrap-metal"));
+        assert_eq!(None, carried.get("wood"));
+        assert_eq!(Some(&6), left_behind.get("scrap-metal"));
+        assert_eq!(Some(&5), left_behind.get("wood"));
+        assert!(get_load(&carried).0 <= 5.0);
+    }
+
+    #[test]
+    fn can_pack_all_loot_within_capacity() {
+        let found = HashMap::from([("medicine".to_owned(), 3)]);
+        let (carried, left_behind) = pack_loot(&found, 20.0, 25.0);
+        assert_eq!(Some(&3), carried.get("medicine"));
+        assert!(left_behind.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use actix::Addr;
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    broadcaster::{Broadcaster, BunkerMessage, Message},
+    data::{FacilityType, FACILITY_TYPES, ITEM_TYPES},
+    db::{
+        bunkers::{Bunker, FacilityStatus},
+        inhabitants::Inhabitant,
+        messages,
+        worlds::Weather,
+    },
+    error, power, repair,
+    util::{roll_dice_with, skill_roll},
+};
+
+const STARTING_PARTS: i32 = 20;
+
+/// Facility types in a stable order.
+pub fn get_facility_types() -> Vec<&'static FacilityType> {
+    let mut facility_types: Vec<&FacilityType> = FACILITY_TYPES.values().collect();
+    facility_types.sort_by_key(|t| &t.id);
+    facility_types
+}
+
+/// Facilities of a newly created bunker.
+pub fn create_facilities() -> HashMap<String, FacilityStatus> {
+    FACILITY_TYPES
+        .values()
+        .map(|facility_type| {
+            let status = FacilityStatus {
+                maintenance: 100,
+                parts: if facility_type.parts_item.is_some() {
+                    STARTING_PARTS
+                } else {
+                    0
+                },
+                ..Default::default()
+            };
+            (facility_type.id.clone(), status)
+        })
+        .collect()
+}
+
+/// Runs maintenance, malfunctions and repairs for all facilities and returns the output metrics
+/// reported by them.
+pub async fn handle_tick(
+    pool: &PgPool,
+    broadcaster: &Addr<Broadcaster>,
+    bunker: &mut Bunker,
+    inhabitants: &mut Vec<Inhabitant>,
+    weather: Weather,
+) -> Result<HashMap<String, i32>, error::Error> {
+    let mut outputs = HashMap::new();
+    for facility_type in get_facility_types() {
+        let power_level = power::get_supply(&bunker.data.power, &facility_type.id);
+        let status = bunker
+            .data
+            .facilities
+            .entry(facility_type.id.clone())
+            .or_insert_with(|| FacilityStatus {
+                maintenance: 100,
+                ..Default::default()
+            });
+        if status.destroyed {
+            continue;
+        }
+        let existing_malfunction = status.malfunction;
+        wear(
+            &mut rand::thread_rng(),
+            facility_type,
+            status,
+            weather,
+            power_level,
+        );
+        if let (Some(assignment), Some(skill), false) = (
+            facility_type.assignment,
+            facility_type.skill,
+            status.evacuated,
+        ) {
+            let workers = inhabitants
+                .iter_mut()
+                .filter(|i| i.is_ready() && i.data.assignment == Some(assignment));
+            for inhabitant in workers {
+                if !status.malfunction && status.maintenance >= 100 {
+                    break;
+                }
+                if inhabitant.data.sleeping {
+                    if status.malfunction {
+                        inhabitant.data.sleep_block = 2;
+                        inhabitant.data.sleeping = false;
+                    } else {
+                        continue;
+                    }
+                }
+                let level = inhabitant.get_skill_level(skill);
+                if skill_roll(0.1, level) {
+                    let improvement = status.repair(level);
+                    // Failed attempts at improvising a fix give no experience
+                    if improvement > 0 {
+                        inhabitant.add_xp(skill, improvement * 10);
+                    }
+                    inhabitant.changed = true;
+                }
+            }
+        }
+        if existing_malfunction && !status.malfunction {
+            messages::create_system_message(
+                pool,
+                &messages::NewSystemMessage {
+                    receiver_bunker_id: bunker.id,
+                    sender_name: format!("{} team", facility_type.name),
+                    subject: format!("{} report", facility_type.name),
+                    body: facility_type.repaired_text.clone(),
+                },
+            )
+            .await?;
+        } else if !existing_malfunction && status.malfunction {
+            let part_name = facility_type
+                .parts_item
+                .as_ref()
+                .and_then(|id| ITEM_TYPES.get(id))
+                .map(|item_type| item_type.name.to_lowercase())
+                .unwrap_or_default();
+            messages::create_system_message(
+                pool,
+                &messages::NewSystemMessage {
+                    receiver_bunker_id: bunker.id,
+                    sender_name: format!("{} warning system", facility_type.name),
+                    subject: format!("{} malfunction", facility_type.name),
+                    body: format!(
+                        "{}{}",
+                        facility_type.malfunction_text,
+                        repair::get_severity_note(status.severe, &part_name)
+                    ),
+                },
+            )
+            .await?;
+            broadcaster.do_send(BunkerMessage {
+                bunker_id: bunker.id,
+                message: Message::Malfunction {
+                    facility: facility_type.id.clone(),
+                },
+            });
+        }
+        if let Some(metric) = &facility_type.output {
+            outputs.insert(
+                metric.clone(),
+                get_output(facility_type, status, power_level),
+            );
+        }
+    }
+    Ok(outputs)
+}
+
+/// Applies a tick of decay to the facility and rolls for a malfunction, which gets more likely as
+/// maintenance and power drop.
+fn wear<R: Rng>(
+    rng: &mut R,
+    facility_type: &FacilityType,
+    status: &mut FacilityStatus,
+    weather: Weather,
+    power_level: i32,
+) {
+    let decay = if facility_type.weather_load {
+        facility_type.decay * weather.air_load()
+    } else {
+        facility_type.decay
+    };
+    status.maintenance = (status.maintenance - decay).max(0);
+    if !status.malfunction && facility_type.malfunction_chance > 0.0 {
+        let neglect = 1.0 - (status.maintenance * power_level / 100) as f64 / 100.0;
+        let rolls = neglect.max(0.0).powf(facility_type.malfunction_exponent) * 100.0;
+        if roll_dice_with(rng, facility_type.malfunction_chance, rolls.round() as i32) {
+            status.break_down();
+        }
+    }
+}
+
+/// Output metric of the facility in percent.
+fn get_output(facility_type: &FacilityType, status: &FacilityStatus, power_level: i32) -> i32 {
+    let output = if status.malfunction {
+        facility_type.malfunction_output
+    } else {
+        100
+    };
+    // Without full power the facility can only process part of the demand
+    output * power_level / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    #[test]
+    fn neglected_facility_breaks_down_until_repaired() {
+        let facility_type = &FACILITY_TYPES["water-treatment"];
+        let mut status = FacilityStatus {
+            maintenance: 0,
+            parts: 1,
+            ..Default::default()
+        };
+        wear(
+            &mut StepRng::new(0, 0),
+            facility_type,
+            &mut status,
+            Weather::default(),
+            100,
+        );
+        assert!(status.malfunction);
+        assert_eq!(
+            facility_type.malfunction_output,
+            get_output(facility_type, &status, 100)
+        );
+        assert!(status.repair(0) > 0);
+        assert!(!status.malfunction);
+        assert_eq!(0, status.parts);
+        assert_eq!(100, get_output(facility_type, &status, 100));
+    }
+
+    #[test]
+    fn maintained_facility_keeps_working() {
+        let facility_type = &FACILITY_TYPES["water-treatment"];
+        let mut status = FacilityStatus {
+            maintenance: 100 + facility_type.decay,
+            ..Default::default()
+        };
+        wear(
+            &mut StepRng::new(0, 0),
+            facility_type,
+            &mut status,
+            Weather::default(),
+            100,
+        );
+        assert!(!status.malfunction);
+        assert_eq!(100, status.maintenance);
+    }
+
+    #[test]
+    fn output_follows_power_supply() {
+        let facility_type = &FACILITY_TYPES["air-recycling"];
+        let mut status = FacilityStatus::default();
+        assert_eq!(60, get_output(facility_type, &status, 60));
+        status.malfunction = true;
+        assert_eq!(
+            facility_type.malfunction_output / 2,
+            get_output(facility_type, &status, 50)
+        );
+    }
+}
@@ -13,18 +13,20 @@ use tracing::info;
 
 use crate::{
     auth::validate_session,
-    broadcaster,
-    data::ITEM_TYPES,
+    broadcaster, cafeteria, construction, contamination,
+    data::{ITEM_TYPES, MEAL_TYPES},
     db::{
+        battles,
         bunkers::{self, Bunker},
         expeditions,
         inhabitants::{self, get_age, Assignment},
-        items, locations, messages,
+        items, locations, messages, recruits,
         sessions::Session,
         worlds,
     },
-    dto::{BunkerDto, ExpeditionDto, InhabitantDto, ItemDto, LocationDto},
-    error, expedition, horticulture, infirmary, reactor, workshop,
+    dto::{BunkerDto, ExpeditionDto, InhabitantDto, ItemDto, LocationDto, RecruitDto},
+    error, expedition, horticulture, infirmary, map, power, rationing, reactor, recruit, repair,
+    terrain, weather, workshop, worldgen,
 };
 
 pub struct Player {
@@ -57,29 +59,67 @@ struct SetAssignmentRequest {
     assignment: Option<Assignment>,
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BattlesRequest {
+    expedition_id: i32,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AcceptRecruitRequest {
+    recruit_id: i32,
+    #[serde(default)]
+    quarantine: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RejectRecruitRequest {
+    recruit_id: i32,
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(get_world)
         .service(get_bunker)
         .service(get_inhabitants)
         .service(set_team)
         .service(set_assignment)
+        .service(get_recruits)
+        .service(accept_recruit)
+        .service(reject_recruit)
         .service(get_items)
         .service(get_locations)
         .service(get_sectors)
+        .service(get_weather)
+        .service(get_map_tile)
         .service(get_messages)
         .service(set_message_read)
         .service(set_all_message_read)
         .service(has_unread_messages)
         .service(get_expeditions)
         .service(create_expedition)
+        .service(recall_expedition)
+        .service(get_battles)
         .service(refuel_reactor)
+        .service(set_power_priorities)
+        .service(set_reactor_evacuation)
+        .service(rebuild_reactor)
         .service(update_infirmary_inventory)
+        .service(update_spare_parts)
+        .service(update_shielded_storage)
         .service(add_crop)
         .service(remove_crop)
         .service(add_project)
         .service(remove_project)
+        .service(start_construction)
+        .service(cancel_construction)
+        .service(set_meal_plan)
+        .service(set_rationing)
+        .service(get_food_projection)
         .service(prioritize_project)
         .service(get_item_types)
+        .service(get_meal_types)
         .service(leave)
         .service(broadcast)
         .service(get_events);
@@ -162,6 +202,74 @@ async fn set_assignment(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/world/{world_id:\\d+}/get_recruits")]
+async fn get_recruits(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    let recruits: Vec<RecruitDto> = recruits::get_recruits(&pool, player.bunker.id)
+        .await?
+        .into_iter()
+        .map(|r| r.into())
+        .collect();
+    Ok(HttpResponse::Ok().json(recruits))
+}
+
+#[post("/world/{world_id:\\d+}/accept_recruit")]
+async fn accept_recruit(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<AcceptRecruitRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    let inhabitant_id =
+        recruit::accept(&pool, &player.bunker, data.recruit_id, data.quarantine).await?;
+    Ok(HttpResponse::Ok().json(inhabitant_id))
+}
+
+#[post("/world/{world_id:\\d+}/reject_recruit")]
+async fn reject_recruit(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<RejectRecruitRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    recruit::reject(&pool, player.bunker.id, data.recruit_id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/get_weather")]
+async fn get_weather(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    let world_time = worlds::get_world_time(&pool, player.world_id).await?;
+    Ok(HttpResponse::Ok().json(weather::get_report(&world_time)))
+}
+
+#[get("/world/{world_id:\\d+}/tiles/{zoom:\\d+}/{x:\\d+}/{y:\\d+}")]
+async fn get_map_tile(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, u32, i32, i32)>,
+) -> actix_web::Result<HttpResponse> {
+    let (world_id, zoom, x, y) = path.into_inner();
+    let player = validate_player(&request, world_id).await?;
+    let map = terrain::get_world_map(&pool, player.world_id).await?;
+    let sectors = locations::get_explored_sectors(&pool, player.bunker.id).await?;
+    let discovered = locations::get_discovered_locations(&pool, player.bunker.id).await?;
+    let tile = map::render_tile(&map, zoom, (x, y), &sectors, &discovered, Utc::now())?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .body(worldgen::encode_png(&tile)?))
+}
+
 #[post("/world/{world_id:\\d+}/get_items")]
 async fn get_items(
     request: HttpRequest,
@@ -275,6 +383,36 @@ async fn create_expedition(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/world/{world_id:\\d+}/recall_expedition")]
+async fn recall_expedition(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<expedition::RecallRequest>,
+    broadcaster: web::Data<Addr<broadcaster::Broadcaster>>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    expedition::recall(&pool, &player.bunker, &data).await?;
+    broadcaster.do_send(broadcaster::BunkerMessage {
+        bunker_id: player.bunker.id,
+        message: broadcaster::Message::Expedition,
+    });
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/get_battles")]
+async fn get_battles(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<BattlesRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    let battles =
+        battles::get_expedition_battles(&pool, player.bunker.id, data.expedition_id).await?;
+    Ok(HttpResponse::Ok().json(battles))
+}
+
 #[post("/world/{world_id:\\d+}/refuel_reactor")]
 async fn refuel_reactor(
     request: HttpRequest,
@@ -287,6 +425,41 @@ async fn refuel_reactor(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/world/{world_id:\\d+}/set_reactor_evacuation")]
+async fn set_reactor_evacuation(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<reactor::EvacuationRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    reactor::set_evacuation(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/rebuild_reactor")]
+async fn rebuild_reactor(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    reactor::rebuild(&pool, &mut player.bunker).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/set_power_priorities")]
+async fn set_power_priorities(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<power::PowerPriorityRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    power::set_priorities(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[post("/world/{world_id:\\d+}/update_infirmary_inventory")]
 async fn update_infirmary_inventory(
     request: HttpRequest,
@@ -299,6 +472,67 @@ async fn update_infirmary_inventory(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/world/{world_id:\\d+}/update_spare_parts")]
+async fn update_spare_parts(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<repair::UpdatePartsRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    repair::update_parts(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/update_shielded_storage")]
+async fn update_shielded_storage(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<contamination::UpdateShieldedStorageRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    contamination::update_shielded_storage(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/set_meal_plan")]
+async fn set_meal_plan(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<cafeteria::MealPlanRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    cafeteria::set_meal_plan(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/set_rationing")]
+async fn set_rationing(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<rationing::RationingRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    rationing::set_rationing(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/get_food_projection")]
+async fn get_food_projection(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+) -> actix_web::Result<HttpResponse> {
+    let player = validate_player(&request, world_id.into_inner()).await?;
+    let world_time = worlds::get_world_time(&pool, player.world_id).await?;
+    let projection =
+        rationing::get_food_projection(&pool, &player.bunker, world_time.now()).await?;
+    Ok(HttpResponse::Ok().json(projection))
+}
+
 #[post("/world/{world_id:\\d+}/add_crop")]
 async fn add_crop(
     request: HttpRequest,
@@ -347,6 +581,30 @@ async fn remove_project(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/world/{world_id:\\d+}/start_construction")]
+async fn start_construction(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<construction::ConstructionRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    construction::start_construction(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/world/{world_id:\\d+}/cancel_construction")]
+async fn cancel_construction(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    world_id: web::Path<i32>,
+    data: web::Json<construction::ConstructionCancellationRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let mut player = validate_player(&request, world_id.into_inner()).await?;
+    construction::cancel_construction(&pool, &mut player.bunker, &data).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[post("/world/{world_id:\\d+}/prioritize_project")]
 async fn prioritize_project(
     request: HttpRequest,
@@ -377,6 +635,7 @@ async fn leave(
 #[post("/world/{world_id:\\d+}/broadcast")]
 async fn broadcast(
     request: HttpRequest,
+    pool: web::Data<PgPool>,
     world_id: web::Path<i32>,
     data: web::Json<String>,
     broadcaster: web::Data<Addr<broadcaster::Broadcaster>>,
@@ -385,6 +644,13 @@ async fn broadcast(
         Err(error::client_error("TOO_LONG"))?;
     }
     let player = validate_player(&request, world_id.into_inner()).await?;
+    bunkers::set_security_field(
+        &pool,
+        player.bunker.id,
+        "lastBroadcast",
+        serde_json::json!(Utc::now()),
+    )
+    .await?;
     broadcaster.do_send(broadcaster::WorldMessage {
         world_id: player.world_id,
         message: broadcaster::Message::Broadcast {
@@ -401,6 +667,11 @@ async fn get_item_types() -> actix_web::Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(ITEM_TYPES.values().collect_vec()))
 }
 
+#[post("/world/{world_id:\\d+}/get_meal_types")]
+async fn get_meal_types() -> actix_web::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(MEAL_TYPES.values().collect_vec()))
+}
+
 #[get("/events")]
 async fn get_events(
     request: HttpRequest,
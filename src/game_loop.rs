@@ -11,16 +11,16 @@ use rand::Rng;
 use sqlx::PgPool;
 
 use crate::{
-    air_recycling,
     broadcaster::{Broadcaster, BunkerMessage, Message},
-    cafeteria,
+    cafeteria, construction, contamination,
     db::{
-        bunkers,
+        bunkers, expeditions,
         inhabitants::{self, Assignment},
         items, messages,
         worlds::{self, WorldTime},
     },
-    error, expedition, health, horticulture, infirmary, reactor, water_treatment, workshop,
+    error, expedition, facility, hazard, health, horticulture, infirmary, location, power, raid,
+    reactor, storage, weather, workshop, world_event,
 };
 
 pub fn start_loop(pool: PgPool, broadcaster: Addr<Broadcaster>) {
@@ -34,8 +34,12 @@ pub fn start_loop(pool: PgPool, broadcaster: Addr<Broadcaster>) {
 }
 
 pub async fn tick(pool: &PgPool, broadcaster: &Addr<Broadcaster>) -> Result<(), error::Error> {
-    let worlds = worlds::get_world_times(pool).await?;
-    for world in &worlds {
+    let mut worlds = worlds::get_world_times(pool).await?;
+    for world in &mut worlds {
+        hazard::handle_tick(pool, world, broadcaster).await?;
+        location::handle_tick(pool, world).await?;
+        weather::handle_tick(pool, world).await?;
+        world_event::handle_tick(pool, world, broadcaster).await?;
         world_tick(pool, world, broadcaster).await?;
     }
     Ok(())
@@ -49,26 +53,51 @@ pub async fn world_tick(
     let bunkers = bunkers::get_bunkers_by_next_tick(pool, world.id).await?;
     for mut bunker in bunkers {
         let mut inhabitants = inhabitants::get_inhabitants(pool, bunker.id).await?;
+        let expedition_hazards: HashMap<i32, i32> = expeditions::get_expeditions(pool, bunker.id)
+            .await?
+            .into_iter()
+            .map(|e| (e.id, e.data.hazard))
+            .collect();
 
-        let power_level = reactor::handle_tick(pool, &mut bunker, &mut inhabitants).await?;
-        let water_quality =
-            water_treatment::handle_tick(pool, &mut bunker, &mut inhabitants, power_level).await?;
-        let air_quality =
-            air_recycling::handle_tick(pool, &mut bunker, &mut inhabitants, power_level).await?;
+        reactor::handle_tick(pool, broadcaster, &mut bunker, &mut inhabitants).await?;
+        power::handle_tick(pool, &mut bunker).await?;
+        let outputs = facility::handle_tick(
+            pool,
+            broadcaster,
+            &mut bunker,
+            &mut inhabitants,
+            world.data.weather,
+        )
+        .await?;
+        let water_quality = outputs.get("water_quality").copied().unwrap_or(100);
+        let air_quality = outputs.get("air_quality").copied().unwrap_or(100);
+        let horticulture_power = power::get_supply(&bunker.data.power, "horticulture");
+        let cafeteria_power = power::get_supply(&bunker.data.power, "cafeteria");
 
         horticulture::handle_tick(
             pool,
             &mut bunker,
             &mut inhabitants,
-            power_level,
+            horticulture_power,
             water_quality,
         )
         .await?;
-        cafeteria::handle_tick(pool, &mut bunker, &mut inhabitants, power_level).await?;
+        cafeteria::handle_tick(pool, &mut bunker, &mut inhabitants, cafeteria_power).await?;
         workshop::handle_tick(pool, &mut bunker, &mut inhabitants).await?;
+        construction::handle_tick(pool, &mut bunker, &mut inhabitants).await?;
+        storage::handle_tick(pool, &mut bunker).await?;
+        contamination::handle_tick(pool, &mut bunker, &mut inhabitants).await?;
+        raid::handle_tick(pool, world, &mut bunker, &mut inhabitants).await?;
         infirmary::handle_tick(&mut bunker, &mut inhabitants)?;
 
-        health::handle_tick(&mut bunker, &mut inhabitants, water_quality, air_quality)?;
+        health::handle_tick(
+            &mut bunker,
+            &mut inhabitants,
+            water_quality,
+            air_quality,
+            &expedition_hazards,
+            world.now(),
+        )?;
 
         for inhabitant in inhabitants {
             if inhabitant.data.health <= 0 {
@@ -107,6 +136,7 @@ pub async fn world_tick(
             message: Message::Tick,
         });
     }
+    expedition::handle_active_expeditions(pool, world, broadcaster).await?;
     expedition::handle_finished_expeditions(pool, world, broadcaster).await?;
     Ok(())
 }
@@ -4,12 +4,12 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use chrono::{Duration, NaiveDate, NaiveDateTime};
-use image::GenericImageView;
 use rand::{seq::IteratorRandom, Rng};
 
 use crate::{
-    data::{FIRST_NAMES, LAST_NAMES, WORLD_MAP},
+    data::{Placement, FIRST_NAMES, LAST_NAMES},
     db::inhabitants::{get_xp_for_level, InhabitantData, NewInhabitant, Skill, SKILL_TYPES},
+    terrain::{TerrainMap, WORLD_SIZE},
 };
 
 pub fn generate_person(
@@ -61,15 +61,27 @@ pub fn generate_person(
     }
 }
 
-pub fn generate_position() -> (i32, i32) {
+pub fn generate_position(map: &TerrainMap) -> (i32, i32) {
     loop {
-        let x = (rand::random::<f64>() * 2600.0) as i32;
-        let y = (rand::random::<f64>() * 2600.0) as i32;
-        let map_x = x * WORLD_MAP.width() as i32 / 2600;
-        let map_y = y * WORLD_MAP.height() as i32 / 2600;
-        let pixel = WORLD_MAP.get_pixel(map_x as u32, map_y as u32);
-        if pixel.0[0] != 0 || pixel.0[1] != 0 || pixel.0[2] != 0 {
+        let x = (rand::random::<f64>() * WORLD_SIZE as f64) as i32;
+        let y = (rand::random::<f64>() * WORLD_SIZE as f64) as i32;
+        if map.is_habitable(x, y) {
             return (x, y);
         }
     }
 }
+
+/// Generates a position on terrain matching the placement, falling back to any habitable
+/// position if none is found.
+pub fn generate_location_position(map: &TerrainMap, placement: Placement) -> (i32, i32) {
+    for _ in 0..1000 {
+        let (x, y) = generate_position(map);
+        let urban = map.get_terrain(x, y).urban;
+        match placement {
+            Placement::Urban if !urban => continue,
+            Placement::Rural if urban => continue,
+            _ => return (x, y),
+        }
+    }
+    generate_position(map)
+}
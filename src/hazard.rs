@@ -0,0 +1,116 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use actix::Addr;
+use chrono::{Duration, Utc};
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    broadcaster::{Broadcaster, BunkerMessage, Message},
+    db::{
+        messages, sectors,
+        worlds::{self, WorldTime},
+    },
+    error,
+    terrain::TerrainMap,
+    util::{get_sector_name, roll_dice},
+};
+
+pub async fn generate_hazards(
+    pool: &PgPool,
+    world_id: i32,
+    map: &TerrainMap,
+) -> Result<(), error::Error> {
+    for sector_x in 0..26 {
+        for sector_y in 0..26 {
+            let mut samples = 0;
+            let mut hazard = 0;
+            for x in (sector_x * 100..sector_x * 100 + 100).step_by(10) {
+                for y in (sector_y * 100..sector_y * 100 + 100).step_by(10) {
+                    hazard += map.get_terrain(x, y).hazard;
+                    samples += 1;
+                }
+            }
+            hazard /= samples;
+            if roll_dice(0.05, 1) {
+                hazard += rand::thread_rng().gen_range(20..60);
+            }
+            if hazard > 0 {
+                sectors::set_sector_hazard(pool, world_id, sector_x, sector_y, hazard.min(100))
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn handle_tick(
+    pool: &PgPool,
+    world: &mut WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    let now = Utc::now();
+    match world.data.next_fallout {
+        Some(next_fallout) if next_fallout > now => return Ok(()),
+        Some(_) => fallout(pool, world, broadcaster).await?,
+        None => (),
+    }
+    let hours = rand::thread_rng().gen_range(48..168);
+    world.data.next_fallout = Some(now + Duration::hours(hours) / world.time_acceleration);
+    worlds::update_world_data(pool, world, &["nextFallout"]).await?;
+    Ok(())
+}
+
+async fn fallout(
+    pool: &PgPool,
+    world: &WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    sectors::decay_hazards(pool, world.id, 5).await?;
+    let mut rng = rand::thread_rng();
+    let center = (rng.gen_range(0..26), rng.gen_range(0..26));
+    let increase = rng.gen_range(20..=50);
+    for (dx, dy, amount) in [
+        (0, 0, increase),
+        (-1, 0, increase / 2),
+        (1, 0, increase / 2),
+        (0, -1, increase / 2),
+        (0, 1, increase / 2),
+    ] {
+        let (x, y) = (center.0 + dx, center.1 + dy);
+        if x < 0 || y < 0 || x >= 26 || y >= 26 {
+            continue;
+        }
+        let hazard = sectors::get_sector_hazard(pool, world.id, x, y).await?;
+        sectors::set_sector_hazard(pool, world.id, x, y, (hazard + amount).min(100)).await?;
+        for bunker_id in sectors::get_bunkers_with_explored_sector(pool, world.id, x, y).await? {
+            messages::create_system_message(
+                pool,
+                &messages::NewSystemMessage {
+                    receiver_bunker_id: bunker_id,
+                    sender_name: format!("Radiation monitoring"),
+                    subject: format!("Fallout detected (Sector {})", get_sector_name((x, y))),
+                    body: format!(
+                        "Increased radiation levels have been detected in sector {}. \
+                        Expeditions to the sector will be more dangerous.",
+                        get_sector_name((x, y))
+                    ),
+                },
+            )
+            .await?;
+            broadcaster.do_send(BunkerMessage {
+                bunker_id,
+                message: Message::Message,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// How much faster surface exposure accumulates in a sector with the given hazard level.
+pub fn get_exposure_multiplier(hazard: i32) -> f64 {
+    1.0 + hazard as f64 / 25.0
+}
@@ -3,32 +3,117 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use rand::Rng;
 use tracing::debug;
 
 use crate::{
-    db::{bunkers::Bunker, inhabitants::Inhabitant},
-    error,
+    db::{
+        bunkers::Bunker,
+        inhabitants::{Inhabitant, InhabitantData, NUTRIENTS},
+    },
+    error, rationing,
     util::roll_dice,
 };
 
+/// Hours between meals.
+pub const MEAL_HOURS: i32 = 12;
+
+/// Hunger after which an inhabitant without food starts starving.
+const STARVATION_HUNGER: i32 = 36;
+
+/// Most hunger that smaller rations can leave over.
+const MAX_UNDERFED: i32 = 18;
+
+/// Hours without a nutrient before deficiency sets in.
+const DEFICIENCY_HOURS: i32 = 7 * 24;
+
+const GOOD_MEAL_QUALITY: i32 = 75;
+
 pub fn handle_tick(
     bunker: &mut Bunker,
     inhabitants: &mut Vec<Inhabitant>,
     water_quality: i32,
     air_quality: i32,
+    expedition_hazards: &HashMap<i32, i32>,
+    now: NaiveDateTime,
 ) -> Result<(), error::Error> {
-    for inhabitant in inhabitants {
+    let contagious = inhabitants
+        .iter()
+        .filter(|i| i.data.contagious && !i.data.quarantined && i.expedition_id.is_none())
+        .count() as i32;
+    let mut rng = rand::thread_rng();
+    let portion_sizes: HashMap<i32, f64> = inhabitants
+        .iter()
+        .map(|i| (i.id, rationing::get_portion_size(bunker, i, now)))
+        .collect();
+    // Feed the priority groups first in case there isn't enough for everyone
+    let groups = bunker.data.cafeteria.priority_groups.clone();
+    let mut order: Vec<usize> = (0..inhabitants.len()).collect();
+    order.sort_by_key(|index| rationing::get_priority(&groups, &inhabitants[*index], now));
+    for index in order {
+        let inhabitant = &mut inhabitants[index];
         inhabitant.data.hunger += 1;
-        inhabitant.data.tiredness += 1;
-        if inhabitant.data.hunger >= 12 {
+        if inhabitant.data.hunger >= MEAL_HOURS {
             if inhabitant.expedition_id.is_none() && bunker.data.cafeteria.food > 0 {
-                bunker.data.cafeteria.food -= 1;
-                inhabitant.data.hunger -= 12;
+                let quality = bunker.data.cafeteria.quality;
+                let portion_size = portion_sizes[&inhabitant.id];
+                // Smaller rations only use up a portion some of the time
+                if rng.gen::<f64>() < portion_size {
+                    for nutrient in bunker.data.cafeteria.take_portion(&mut rng) {
+                        inhabitant.data.nutrition.insert(nutrient, 0);
+                    }
+                }
+                inhabitant.data.hunger -= MEAL_HOURS;
+                inhabitant.data.underfed = get_underfed(inhabitant.data.underfed, portion_size);
                 inhabitant.data.starving = false;
-            } else if inhabitant.data.hunger >= 36 {
+                // A good meal helps recovery
+                if quality >= GOOD_MEAL_QUALITY && inhabitant.data.health < 100 {
+                    inhabitant.data.health += 1;
+                }
+            } else if inhabitant.data.hunger + inhabitant.data.underfed >= STARVATION_HUNGER {
                 inhabitant.data.starving = true;
             }
         }
+    }
+    for inhabitant in inhabitants {
+        let hazard = inhabitant
+            .expedition_id
+            .and_then(|id| expedition_hazards.get(&id))
+            .copied()
+            .unwrap_or(0);
+        inhabitant.data.tiredness += 1;
+        let portion_size = portion_sizes[&inhabitant.id];
+        if inhabitant.expedition_id.is_none() && portion_size < 1.0 {
+            // Underfed inhabitants tire faster and get less work done
+            if roll_dice(1.0 - portion_size, 1) {
+                inhabitant.data.tiredness += 1;
+            }
+        }
+        if inhabitant.data.underfed >= MEAL_HOURS && roll_dice(0.05, 1) {
+            inhabitant.data.health -= 1;
+            inhabitant.changed = true;
+        }
+        for nutrient in NUTRIENTS {
+            *inhabitant.data.nutrition.entry(nutrient).or_insert(0) += 1;
+        }
+        let malnourished = inhabitant
+            .data
+            .nutrition
+            .values()
+            .any(|hours| *hours > DEFICIENCY_HOURS);
+        if malnourished != inhabitant.data.malnourished {
+            inhabitant.data.malnourished = malnourished;
+            inhabitant.changed = true;
+        }
+        // A monotonous diet wears people down, morale slowly recovers on a varied one
+        if malnourished {
+            inhabitant.data.morale = (inhabitant.data.morale - 1).max(-100);
+        } else if inhabitant.data.morale < 0 {
+            inhabitant.data.morale += 1;
+        }
         if inhabitant.expedition_id.is_none() {
             if air_quality < 100 || water_quality < 100 {
                 if air_quality < 100 && water_quality < 100 {
@@ -80,6 +165,10 @@ pub fn handle_tick(
             inhabitant.data.health -= 1;
             inhabitant.changed = true;
         }
+        if inhabitant.data.malnourished && roll_dice(0.1, 1) {
+            inhabitant.data.health -= 1;
+            inhabitant.changed = true;
+        }
         if inhabitant.data.sick {
             if inhabitant.data.health >= 25
                 && inhabitant.data.surface_exposure < 1
@@ -87,25 +176,32 @@ pub fn handle_tick(
             {
                 debug!("{} recovered from disease", inhabitant.name);
                 inhabitant.data.sick = false;
+                inhabitant.data.contagious = false;
             } else {
                 inhabitant.data.health -= 2;
             }
             inhabitant.changed = true;
         } else if roll_dice(
             0.01,
-            inhabitant.data.surface_exposure + (inhabitant.data.tiredness - 24).max(0),
+            inhabitant.data.surface_exposure
+                + (inhabitant.data.tiredness - 24).max(0)
+                + hazard / 10,
         ) {
             debug!("{} got sick", inhabitant.name);
             inhabitant.data.sick = true;
             inhabitant.data.recovering = false;
             inhabitant.changed = true;
-        }
-        if !inhabitant.data.bleeding
-            && !inhabitant.data.infection
-            && !inhabitant.data.wounded
-            && !inhabitant.data.sick
-            && !inhabitant.data.starving
+        } else if inhabitant.expedition_id.is_none()
+            && !inhabitant.data.quarantined
+            && roll_dice(0.005, contagious)
         {
+            debug!("{} caught a disease", inhabitant.name);
+            inhabitant.data.sick = true;
+            inhabitant.data.contagious = true;
+            inhabitant.data.recovering = false;
+            inhabitant.changed = true;
+        }
+        if can_recover(&inhabitant.data) {
             if inhabitant.data.health < 100 {
                 inhabitant.data.health += 1;
                 inhabitant.data.recovering = true;
@@ -121,3 +217,53 @@ pub fn handle_tick(
     }
     Ok(())
 }
+
+/// Health only recovers without injuries, sickness or lack of food.
+fn can_recover(data: &InhabitantData) -> bool {
+    !data.bleeding
+        && !data.infection
+        && !data.wounded
+        && !data.sick
+        && !data.starving
+        && !data.malnourished
+        && data.underfed < MEAL_HOURS
+}
+
+/// Hunger left over after a meal of the given portion size. Smaller rations add the missing part
+/// of the meal, full rations slowly make up for it.
+fn get_underfed(underfed: i32, portion_size: f64) -> i32 {
+    if portion_size < 1.0 {
+        (underfed + (MEAL_HOURS as f64 * (1.0 - portion_size)).round() as i32).min(MAX_UNDERFED)
+    } else {
+        (underfed - MEAL_HOURS / 2).max(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::bunkers::Rationing;
+
+    #[test]
+    fn smaller_rations_leave_hunger() {
+        assert_eq!(3, get_underfed(0, Rationing::Reduced.portion_size()));
+        assert_eq!(6, get_underfed(0, Rationing::Survival.portion_size()));
+        assert_eq!(
+            MAX_UNDERFED,
+            get_underfed(15, Rationing::Survival.portion_size())
+        );
+        assert_eq!(4, get_underfed(10, Rationing::Full.portion_size()));
+        assert_eq!(0, get_underfed(4, Rationing::Full.portion_size()));
+    }
+
+    #[test]
+    fn underfed_inhabitants_do_not_recover() {
+        let mut data = InhabitantData {
+            health: 50,
+            ..Default::default()
+        };
+        assert!(can_recover(&data));
+        data.underfed = MEAL_HOURS;
+        assert!(!can_recover(&data));
+    }
+}
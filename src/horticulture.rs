@@ -3,20 +3,27 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use rand::Rng;
 use sqlx::PgPool;
 use tracing::warn;
 
 use crate::{
-    data::ITEM_TYPES,
+    construction,
+    data::{RoomEffect, ITEM_TYPES},
     db::{
         bunkers::{self, Bunker, Crop},
         inhabitants::{Assignment, Inhabitant, SkillType},
-        items,
+        items, messages,
     },
-    error,
-    util::{roll_dice, skill_roll},
+    error, storage,
+    util::{roll_dice, roll_dice_with, skill_roll, skill_roll_with},
 };
 
+const PESTICIDE: &str = "pesticide";
+
+/// Chance per tick of an affected crop spreading disease or pests to another crop.
+const SPREAD_CHANCE: f64 = 0.01;
+
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NewCropRequest {
@@ -42,11 +49,52 @@ pub async fn handle_tick(
         .filter(|i| i.is_ready() && i.data.assignment == Some(Assignment::Horticulture))
         .collect();
     let mut harvestable = workers.len() * 2;
+    let mut free_space = storage::get_free_space(pool, bunker).await?;
+    let botany_level = workers
+        .iter()
+        .filter(|w| !w.data.sleeping)
+        .map(|w| w.get_skill_level(SkillType::Botany))
+        .max();
+    // Attentive care keeps outbreaks from spreading
+    let spread_chance = SPREAD_CHANCE / (1 + botany_level.map_or(0, |level| level + 1)) as f64;
+    let crops = &bunker.data.horticulture.crops;
+    let diseased = crops.iter().filter(|c| c.diseased).count() as i32;
+    let infested = crops.iter().filter(|c| c.pests).count() as i32;
+    let mut outbreak = false;
+    let mut treated = false;
+    let mut rng = rand::thread_rng();
     for crop in &mut bunker.data.horticulture.crops {
         let crop_type = ITEM_TYPES
             .get(&crop.seed_type)
             .ok_or_else(|| error::internal_error("Unknown crop type"))?;
-        if roll_dice(1.0 / 24.0, 1) {
+        outbreak |= spread(
+            &mut rng,
+            crop,
+            spread_chance,
+            diseased,
+            infested,
+            water_quality,
+        );
+        if crop.stage < 0 {
+            continue;
+        }
+        if crop.diseased || crop.pests {
+            let worker = workers.iter_mut().find(|w| !w.data.sleeping);
+            if let Some(worker) = worker {
+                let level = worker.get_skill_level(SkillType::Botany);
+                if can_treat(&mut rng, crop, level)
+                    && items::remove_item(pool, bunker.id, PESTICIDE, 1).await?
+                {
+                    crop.diseased = false;
+                    crop.pests = false;
+                    treated = true;
+                    worker.add_xp(SkillType::Botany, 40);
+                    worker.changed = true;
+                }
+            }
+        }
+        // Pests keep the crop from growing
+        if roll_dice(1.0 / 24.0, 1) && !crop.pests {
             if crop.stunted {
                 crop.stage -= 1;
             } else {
@@ -79,9 +127,14 @@ pub async fn handle_tick(
             let chance = crop.quantity as f64 / crop_type.growth_time as f64 / 24.0;
             if roll_dice(chance, 1) {
                 if let Some(produce) = &crop_type.produce {
-                    if harvestable > 0 {
+                    let volume = ITEM_TYPES.get(produce).map(|t| t.volume).unwrap_or(0.0);
+                    if harvestable > 0 && volume <= free_space {
                         harvestable -= 1;
+                        free_space -= volume;
                         items::add_item(pool, bunker.id, produce, 1).await?;
+                        if can_save_seed(&mut rng, crop, botany_level) {
+                            items::add_item(pool, bunker.id, &crop.seed_type, 1).await?;
+                        }
                     }
                 }
             }
@@ -92,9 +145,76 @@ pub async fn handle_tick(
         .horticulture
         .crops
         .retain(|crop| crop.stage >= 0);
+    if treated {
+        items::remove_empty_items(pool, bunker.id).await?;
+    }
+    if outbreak {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Horticulture team"),
+                subject: format!("Crop outbreak"),
+                body: format!(
+                    "Disease or pests have been found among the crops and may spread to the rest \
+                    of the horticulture bay. The team needs pesticide to treat the affected crops, \
+                    otherwise they should be removed."
+                ),
+            },
+        )
+        .await?;
+    }
     Ok(())
 }
 
+/// Rolls for disease and pests spreading to the crop, given the number of crops already affected,
+/// and for disease killing off plants. A crop that dies is marked with stage -1. Returns true when
+/// a surviving crop is the first to be affected.
+fn spread<R: Rng>(
+    rng: &mut R,
+    crop: &mut Crop,
+    spread_chance: f64,
+    diseased: i32,
+    infested: i32,
+    water_quality: i32,
+) -> bool {
+    let mut outbreak = false;
+    if !crop.diseased
+        && (roll_dice_with(rng, 0.001, 1 + (100 - water_quality) / 10)
+            || roll_dice_with(rng, spread_chance, diseased))
+    {
+        crop.diseased = true;
+        outbreak |= diseased == 0;
+    }
+    if !crop.pests
+        && (roll_dice_with(rng, 0.001, 1) || roll_dice_with(rng, spread_chance, infested))
+    {
+        crop.pests = true;
+        outbreak |= infested == 0;
+    }
+    if crop.diseased && roll_dice_with(rng, 0.02, 1) {
+        crop.quantity -= 1;
+        if crop.quantity < 1 {
+            crop.stage = -1;
+            return false;
+        }
+    }
+    outbreak
+}
+
+/// Rolls for a worker treating a diseased or infested crop, which also takes a pesticide.
+fn can_treat<R: Rng>(rng: &mut R, crop: &Crop, botany_level: i32) -> bool {
+    (crop.diseased || crop.pests) && skill_roll_with(rng, 0.1, botany_level)
+}
+
+/// Rolls for saving a seed when harvesting. Seeds can only be saved from healthy plants.
+fn can_save_seed<R: Rng>(rng: &mut R, crop: &Crop, botany_level: Option<i32>) -> bool {
+    match botany_level {
+        Some(level) if !crop.diseased => skill_roll_with(rng, 0.02, level),
+        _ => false,
+    }
+}
+
 pub async fn remove_crop(
     pool: &PgPool,
     bunker: &mut Bunker,
@@ -115,7 +235,9 @@ pub async fn add_crop(
     bunker: &mut Bunker,
     request: &NewCropRequest,
 ) -> Result<(), error::Error> {
-    if bunker.data.horticulture.crops.len() >= 6 {
+    if bunker.data.horticulture.crops.len() as i32
+        >= construction::get_capacity(&bunker.data, RoomEffect::Crops)
+    {
         Err(error::client_error("TOO_MANY_CROPS"))?;
     }
     if request.amount < 1 {
@@ -142,6 +264,7 @@ pub async fn add_crop(
         max: seed_type.growth_time,
         stunted: false,
         diseased: false,
+        pests: false,
     });
     bunkers::update_bunker_data_query(bunker)
         .execute(&mut tx)
@@ -150,3 +273,66 @@ pub async fn add_crop(
     items::remove_empty_items(pool, bunker.id).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    fn always() -> StepRng {
+        StepRng::new(0, 0)
+    }
+
+    fn never() -> StepRng {
+        StepRng::new(u64::MAX, 0)
+    }
+
+    fn crop(quantity: i32) -> Crop {
+        Crop {
+            seed_type: "carrot-seed".to_owned(),
+            quantity,
+            stage: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_affected_crop_starts_an_outbreak() {
+        let mut healthy = crop(5);
+        assert!(spread(&mut always(), &mut healthy, 0.01, 0, 0, 100));
+        assert!(healthy.diseased && healthy.pests);
+        assert_eq!(4, healthy.quantity);
+        let mut healthy = crop(5);
+        assert!(!spread(&mut always(), &mut healthy, 0.01, 1, 1, 100));
+        assert!(healthy.diseased && healthy.pests);
+        let mut healthy = crop(5);
+        assert!(!spread(&mut never(), &mut healthy, 0.01, 1, 1, 100));
+        assert!(!healthy.diseased && !healthy.pests);
+    }
+
+    #[test]
+    fn crop_killed_by_disease_does_not_start_an_outbreak() {
+        let mut dying = crop(1);
+        assert!(!spread(&mut always(), &mut dying, 0.01, 0, 0, 100));
+        assert_eq!(-1, dying.stage);
+    }
+
+    #[test]
+    fn only_affected_crops_are_treated() {
+        let mut affected = crop(5);
+        assert!(!can_treat(&mut always(), &affected, 0));
+        affected.pests = true;
+        assert!(can_treat(&mut always(), &affected, 0));
+        assert!(!can_treat(&mut never(), &affected, 3));
+    }
+
+    #[test]
+    fn seeds_are_saved_from_healthy_crops_only() {
+        let mut healthy = crop(5);
+        assert!(can_save_seed(&mut always(), &healthy, Some(0)));
+        assert!(!can_save_seed(&mut always(), &healthy, None));
+        healthy.diseased = true;
+        assert!(!can_save_seed(&mut always(), &healthy, Some(0)));
+    }
+}
@@ -9,11 +9,10 @@ use sqlx::PgPool;
 
 use crate::{
     db::{
-        bunkers::{self, Bunker},
+        bunkers::Bunker,
         inhabitants::{Assignment, Inhabitant, SkillType},
-        items,
     },
-    error,
+    error, storage,
     util::{roll_dice, skill_roll},
 };
 
@@ -115,7 +114,10 @@ pub fn handle_tick(
                     Action::StopBleeding => inhabitant.data.bleeding = false,
                     Action::TreatWound => inhabitant.data.wounded = false,
                     Action::StopInfection => inhabitant.data.infection = false,
-                    Action::TreatDisease => inhabitant.data.sick = false,
+                    Action::TreatDisease => {
+                        inhabitant.data.sick = false;
+                        inhabitant.data.contagious = false;
+                    }
                 }
                 inhabitant.data.recovering = !inhabitant.needs_attention();
                 inhabitant.changed = true;
@@ -124,6 +126,13 @@ pub fn handle_tick(
                 inhabitant.changed = true;
             }
         }
+        if inhabitant.data.quarantined
+            && !inhabitant.needs_attention()
+            && inhabitant.data.surface_exposure < 1
+        {
+            inhabitant.data.quarantined = false;
+            inhabitant.changed = true;
+        }
     }
     Ok(())
 }
@@ -139,31 +148,16 @@ pub async fn update_inventory(
     bunker: &mut Bunker,
     request: &UpdateInventoryRequest,
 ) -> Result<(), error::Error> {
-    let mut tx = pool.begin().await?;
-    let existing = bunker.data.infirmary.medicine;
-    if existing < request.medicine {
-        let diff = request.medicine - existing;
-        let affected = items::remove_items_query(bunker.id, "medicine", diff)
-            .execute(&mut tx)
-            .await?
-            .rows_affected();
-        if affected < 1 {
-            Err(error::client_error("MISSING_ITEM"))?;
-        }
-    } else if existing > request.medicine {
-        let diff = existing - request.medicine;
-        items::add_item_query(bunker.id, "medicine", diff)
-            .execute(&mut tx)
-            .await?;
-    } else {
-        return Ok(());
-    }
-    // TODO: SELECT .. FOR UPDATE to lock row
-    bunker.data.infirmary.medicine = request.medicine;
-    bunkers::update_bunker_data_query(bunker)
-        .execute(&mut tx)
-        .await?;
-    tx.commit().await?;
-    items::remove_empty_items(pool, bunker.id).await?;
-    Ok(())
+    storage::update_stock(
+        pool,
+        bunker,
+        "medicine",
+        request.medicine,
+        |data| data.infirmary.medicine,
+        |data| {
+            data.infirmary.medicine = request.medicine;
+            Ok(())
+        },
+    )
+    .await
 }
@@ -3,7 +3,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+
+use actix::Addr;
 use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
 use futures::future::try_join_all;
 use log::warn;
 use rand::{seq::IteratorRandom, Rng};
@@ -11,22 +15,34 @@ use sqlx::PgPool;
 
 use crate::{
     auth::{generate_session_id, validate_admin_session, validate_session},
+    broadcaster::Broadcaster,
     data::{self, ITEM_TYPES, LAST_NAMES},
     db::{
         bunkers::{self, Crop},
-        inhabitants::{self, get_xp_for_level, Assignment, Skill, SkillType},
+        inhabitants::{self, get_xp_for_level, Assignment, Skill, SkillType, NUTRIENTS},
         items, locations, worlds,
     },
-    error,
+    error, facility,
     game::validate_player,
-    generate::{self, generate_position},
+    generate::{self, generate_location_position, generate_position},
+    hazard, pvp, terrain,
     util::get_sector,
+    world_event, worldgen,
 };
 
 #[derive(serde::Deserialize)]
 struct WorldCreationRequest {
-    name: String,
-    open: bool,
+    #[serde(flatten)]
+    world: worlds::NewWorld,
+    #[serde(default)]
+    generator: Option<worlds::MapParams>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TriggerEventRequest {
+    world_id: i32,
+    event_type: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -38,6 +54,7 @@ struct JoinRequest {
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(get_worlds)
         .service(create_world)
+        .service(trigger_event)
         .service(get_user_worlds)
         .service(join_world);
 }
@@ -55,14 +72,30 @@ async fn get_worlds(
 async fn create_world(
     request: HttpRequest,
     pool: web::Data<PgPool>,
-    data: web::Json<worlds::NewWorld>,
+    data: web::Json<WorldCreationRequest>,
 ) -> actix_web::Result<HttpResponse> {
     validate_admin_session(&request).await?;
     let request_data = data.into_inner();
-    let world_id = worlds::create_world(&pool, &request_data).await?;
+    // The map is generated first so that invalid parameters don't leave a world without a map
+    let generated = match request_data.generator {
+        Some(mut params) => {
+            if params.seed == 0 {
+                params.seed = rand::random();
+            }
+            let png = worldgen::encode_png(&worldgen::generate_map(&params)?)?;
+            Some((params, png))
+        }
+        None => None,
+    };
+    let world_id = worlds::create_world(&pool, &request_data.world).await?;
+    if let Some((params, png)) = generated {
+        worlds::create_world_map(&pool, world_id, &params, &png).await?;
+    }
+    let map = terrain::get_world_map(&pool, world_id).await?;
+    hazard::generate_hazards(&pool, world_id, &map).await?;
     for location_type in data::LOCATION_TYPES.values() {
         for i in 0..location_type.quantity {
-            let (x, y) = generate_position();
+            let (x, y) = generate_location_position(&map, location_type.placement);
             let name = format!("{} {}", location_type.name, i + 1);
             locations::create_location(
                 &pool,
@@ -74,6 +107,8 @@ async fn create_world(
                     data: locations::LocationData {
                         location_type: location_type.id.clone(),
                         searches: 0,
+                        bunker_id: None,
+                        depleted: false,
                     },
                 },
             )
@@ -83,6 +118,25 @@ async fn create_world(
     Ok(HttpResponse::Ok().json(world_id))
 }
 
+#[post("/lobby/trigger_event")]
+async fn trigger_event(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    broadcaster: web::Data<Addr<Broadcaster>>,
+    data: web::Json<TriggerEventRequest>,
+) -> actix_web::Result<HttpResponse> {
+    validate_admin_session(&request).await?;
+    let event_type = data::WORLD_EVENT_TYPES
+        .get(&data.event_type)
+        .ok_or_else(|| error::client_error("INVALID_EVENT_TYPE"))?;
+    let mut world = worlds::get_world_time(&pool, data.world_id).await?;
+    world_event::start_event(&pool, &mut world, &broadcaster, event_type).await?;
+    if let Some(event) = world.data.events.last() {
+        worlds::add_world_event(&pool, world.id, event).await?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[post("/lobby/get_user_worlds")]
 async fn get_user_worlds(
     request: HttpRequest,
@@ -104,7 +158,8 @@ async fn join_world(
     let bunker_number = 1 + bunkers::get_max_bunker_number(&pool, request_data.world_id)
         .await?
         .unwrap_or(0);
-    let (x, y) = generate_position();
+    let map = terrain::get_world_map(&pool, request_data.world_id).await?;
+    let (x, y) = generate_position(&map);
     let mut rng = rand::thread_rng();
     let mut food_required = 25 * 3;
     let mut crops = vec![];
@@ -123,6 +178,7 @@ async fn join_world(
             max: seed_type.growth_time,
             stunted: false,
             diseased: false,
+            pests: false,
         });
         food_required -= quantity / seed_type.growth_time;
     }
@@ -136,26 +192,37 @@ async fn join_world(
             y,
             broadcast_id: generate_session_id(),
             data: bunkers::BunkerData {
+                facilities: facility::create_facilities(),
                 reactor: bunkers::ReactorStatus {
-                    maintenance: 100,
                     fuel: 300,
-                    malfunction: false,
-                    parts: 20,
-                },
-                water_treatment: bunkers::WaterTreatmentStatus {
-                    maintenance: 100,
-                    malfunction: false,
-                    parts: 20,
+                    ..Default::default()
                 },
                 infirmary: bunkers::InfirmaryStatus { medicine: 25 },
                 workshop: bunkers::WorkshopStatus { projects: vec![] },
                 horticulture: bunkers::HorticultureStatus { crops },
-                air_recycling: bunkers::AirRecyclingStatus {
-                    maintenance: 100,
-                    malfunction: false,
-                    parts: 20,
+                cafeteria: bunkers::CafeteriaStatus {
+                    food: 25 * 6,
+                    quality: 50,
+                    nutrients: NUTRIENTS.iter().map(|n| (*n, 25 * 6)).collect(),
+                    ..Default::default()
+                },
+                power: bunkers::PowerGridStatus::default(),
+                shielded_storage: bunkers::ShieldedStorageStatus::default(),
+                storage: bunkers::StorageStatus::default(),
+                rooms: HashMap::new(),
+                construction: vec![],
+                security: bunkers::SecurityStatus {
+                    last_raid: None,
+                    protected_until: if world.pvp {
+                        Some(
+                            Utc::now()
+                                + Duration::days(pvp::PROTECTION_DAYS) / world.time_acceleration,
+                        )
+                    } else {
+                        None
+                    },
+                    ..Default::default()
                 },
-                cafeteria: bunkers::CafeteriaStatus { food: 25 * 6 },
             },
         },
     )
@@ -198,6 +265,8 @@ async fn join_world(
                 Assignment::WaterTreatment => SkillType::Repair,
                 Assignment::AirRecycling => SkillType::Repair,
                 Assignment::Cafeteria => SkillType::Cooking,
+                Assignment::Security => SkillType::Combat,
+                Assignment::Construction => SkillType::Crafting,
             };
             let min_level =
                 (((world_time.date() - person.date_of_birth).num_days() / 365) / 10) as i32;
@@ -227,6 +296,24 @@ async fn join_world(
         inhabitants::create_inhabitant(&pool, bunker_id, &person).await?;
     }
     items::add_item(&pool, bunker_id, "fuel-rod-10", 1).await?;
+    if world.pvp {
+        locations::create_location(
+            &pool,
+            &locations::NewLocation {
+                world_id: world.id,
+                name: format!("Bunker {}", bunker_number),
+                x,
+                y,
+                data: locations::LocationData {
+                    location_type: format!("bunker"),
+                    searches: 0,
+                    bunker_id: Some(bunker_id),
+                    depleted: false,
+                },
+            },
+        )
+        .await?;
+    }
     let sector = get_sector(x, y);
     locations::add_all_bunker_locations_in_sector(&pool, world.id, bunker_id, sector).await?;
     locations::add_bunker_sector(&pool, bunker_id, sector.0, sector.1).await?;
@@ -0,0 +1,198 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    data::{LocationType, LOCATION_TYPES},
+    db::{
+        locations::{self, LocationData},
+        messages, sectors,
+        worlds::{self, WorldTime},
+    },
+    error,
+    generate::generate_location_position,
+    terrain,
+    util::{get_sector, get_sector_name, roll_dice},
+};
+
+pub async fn handle_tick(pool: &PgPool, world: &mut WorldTime) -> Result<(), error::Error> {
+    let now = Utc::now();
+    match world.data.next_location_update {
+        Some(next_update) if next_update > now => return Ok(()),
+        Some(_) => {
+            recover_locations(pool, world).await?;
+            locations::delete_depleted_locations(pool, world.id).await?;
+            spawn_locations(pool, world).await?;
+        }
+        None => (),
+    }
+    world.data.next_location_update = Some(now + Duration::days(1) / world.time_acceleration);
+    worlds::update_world_data(pool, world, &["nextLocationUpdate"]).await?;
+    Ok(())
+}
+
+/// Searched locations slowly fill up with loot again.
+async fn recover_locations(pool: &PgPool, world: &WorldTime) -> Result<(), error::Error> {
+    for mut location in locations::get_searched_locations(pool, world.id).await? {
+        let recovery_days = match LOCATION_TYPES.get(&location.data.location_type) {
+            Some(location_type) if location_type.recovery_days > 0.0 => location_type.recovery_days,
+            _ => continue,
+        };
+        if !roll_dice(1.0 / recovery_days, 1) {
+            continue;
+        }
+        let replenished = recover(&mut location.data);
+        locations::update_location(pool, &location).await?;
+        if !replenished {
+            continue;
+        }
+        let sector_name = get_sector_name(get_sector(location.x, location.y));
+        for bunker_id in locations::get_bunkers_with_discovered_location(pool, location.id).await? {
+            messages::create_system_message(
+                pool,
+                &messages::NewSystemMessage {
+                    receiver_bunker_id: bunker_id,
+                    sender_name: format!("Scouting team"),
+                    subject: format!("Location replenished (Sector {})", sector_name),
+                    body: format!(
+                        "{} in sector {} appears to have been replenished since it was last searched.",
+                        location.name, sector_name
+                    ),
+                },
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// New locations such as supply drops and collapsed buildings appear in the world.
+async fn spawn_locations(pool: &PgPool, world: &WorldTime) -> Result<(), error::Error> {
+    for location_type in LOCATION_TYPES.values() {
+        if location_type.spawn_days <= 0.0 || !roll_dice(1.0 / location_type.spawn_days, 1) {
+            continue;
+        }
+        if location_type.max_spawned > 0
+            && locations::count_locations_of_type(pool, world.id, &location_type.id).await?
+                >= location_type.max_spawned
+        {
+            continue;
+        }
+        let map = terrain::get_world_map(pool, world.id).await?;
+        let (x, y) = generate_location_position(&map, location_type.placement);
+        let name = get_new_location_name(pool, world.id, location_type).await?;
+        let location_id = locations::create_location(
+            pool,
+            &locations::NewLocation {
+                world_id: world.id,
+                name: name.clone(),
+                x,
+                y,
+                data: locations::LocationData {
+                    location_type: location_type.id.clone(),
+                    searches: 0,
+                    bunker_id: None,
+                    depleted: false,
+                },
+            },
+        )
+        .await?;
+        let sector = get_sector(x, y);
+        let sector_name = get_sector_name(sector);
+        for bunker_id in
+            sectors::get_bunkers_with_explored_sector(pool, world.id, sector.0, sector.1).await?
+        {
+            locations::add_bunker_location(pool, bunker_id, location_id).await?;
+            messages::create_system_message(
+                pool,
+                &messages::NewSystemMessage {
+                    receiver_bunker_id: bunker_id,
+                    sender_name: format!("Scouting team"),
+                    subject: format!("New location (Sector {})", sector_name),
+                    body: format!("{} has been spotted in sector {}.", name, sector_name),
+                },
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes one search from a searched location. Returns true when the location is fully
+/// replenished.
+fn recover(location: &mut LocationData) -> bool {
+    location.searches = (location.searches - 1).max(0);
+    location.searches == 0
+}
+
+/// Records a search of the location, depleting single-use locations. Returns true when the
+/// location was depleted.
+pub fn mark_searched(location: &mut LocationData, location_type: &LocationType) -> bool {
+    location.searches += 1;
+    if location_type.single_use {
+        location.depleted = true;
+    }
+    location.depleted
+}
+
+/// Name for a new location of the type, numbered after the existing ones.
+pub async fn get_new_location_name(
+    pool: &PgPool,
+    world_id: i32,
+    location_type: &LocationType,
+) -> Result<String, error::Error> {
+    let names = locations::get_location_names_of_type(pool, world_id, &location_type.id).await?;
+    Ok(get_free_name(&location_type.name, &names))
+}
+
+/// First "{base} {n}" not among the names. Numbers of deleted locations are reused.
+fn get_free_name(base: &str, names: &[String]) -> String {
+    (1..)
+        .map(|n| format!("{} {}", base, n))
+        .find(|name| !names.contains(name))
+        .unwrap_or_else(|| base.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location_data(searches: i32) -> LocationData {
+        LocationData {
+            location_type: "supply-drop".to_owned(),
+            searches,
+            bunker_id: None,
+            depleted: false,
+        }
+    }
+
+    #[test]
+    fn recovery_removes_one_search() {
+        let mut location = location_data(2);
+        assert!(!recover(&mut location));
+        assert_eq!(1, location.searches);
+        assert!(recover(&mut location));
+        assert_eq!(0, location.searches);
+    }
+
+    #[test]
+    fn single_use_locations_are_depleted_by_a_search() {
+        let mut location = location_data(0);
+        assert!(mark_searched(&mut location, &LOCATION_TYPES["supply-drop"]));
+        assert_eq!(1, location.searches);
+        let mut location = location_data(0);
+        assert!(!mark_searched(&mut location, &LOCATION_TYPES["house"]));
+        assert_eq!(1, location.searches);
+    }
+
+    #[test]
+    fn new_names_fill_gaps() {
+        let names = vec!["Supply Drop 1".to_owned(), "Supply Drop 3".to_owned()];
+        assert_eq!("Supply Drop 2", get_free_name("Supply Drop", &names));
+        assert_eq!("Supply Drop 1", get_free_name("Supply Drop", &[]));
+    }
+}
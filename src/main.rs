@@ -15,28 +15,44 @@ extern crate lazy_static;
 
 use crate::settings::Settings;
 
-mod air_recycling;
 mod auth;
 mod battle;
 mod broadcaster;
 mod cafeteria;
+mod construction;
+mod contamination;
 mod data;
 mod db;
 mod dto;
 mod error;
+mod event;
 mod expedition;
+mod facility;
 mod game;
 mod game_loop;
 mod generate;
+mod hazard;
 mod health;
 mod horticulture;
 mod infirmary;
 mod lobby;
+mod location;
+mod map;
+mod power;
+mod pvp;
+mod raid;
+mod rationing;
 mod reactor;
+mod recruit;
+mod repair;
 mod settings;
+mod storage;
+mod terrain;
 mod util;
-mod water_treatment;
+mod weather;
 mod workshop;
+mod world_event;
+mod worldgen;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -50,6 +66,12 @@ async fn main() -> std::io::Result<()> {
     info!("{} last names loaded", data::LAST_NAMES.len());
     info!("{} location types loaded", data::LOCATION_TYPES.len());
     info!("{} item types loaded", data::ITEM_TYPES.len());
+    info!("{} terrain types loaded", data::TERRAIN_TYPES.len());
+    info!("{} enemy types loaded", data::ENEMY_TYPES.len());
+    info!("{} world event types loaded", data::WORLD_EVENT_TYPES.len());
+    info!("{} meal types loaded", data::MEAL_TYPES.len());
+    info!("{} facility types loaded", data::FACILITY_TYPES.len());
+    info!("{} room types loaded", data::ROOM_TYPES.len());
     info!(
         "{}x{} world map loaded",
         data::WORLD_MAP.width(),
@@ -0,0 +1,116 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::{
+    db::locations::{Location, Sector},
+    error,
+    terrain::{TerrainMap, WORLD_SIZE},
+};
+
+pub const TILE_SIZE: u32 = 256;
+pub const MAX_ZOOM: u32 = 4;
+
+const FOG: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const HAZARD: [u8; 3] = [200, 40, 40];
+const LOCATION: Rgba<u8> = Rgba([255, 200, 0, 255]);
+const STALE_DAYS: i64 = 7;
+
+/// Renders a map tile as seen by a bunker. At zoom level `z` the world is divided into
+/// `2^z` by `2^z` tiles. Unexplored sectors are covered in fog, explored sectors are tinted
+/// by their hazard level and dimmed if they haven't been visited recently.
+pub fn render_tile(
+    map: &TerrainMap,
+    zoom: u32,
+    tile: (i32, i32),
+    sectors: &[Sector],
+    locations: &[Location],
+    now: DateTime<Utc>,
+) -> Result<DynamicImage, error::Error> {
+    let tiles = 1 << zoom.min(MAX_ZOOM);
+    if zoom > MAX_ZOOM || tile.0 < 0 || tile.1 < 0 || tile.0 >= tiles || tile.1 >= tiles {
+        Err(error::client_error("INVALID_TILE"))?;
+    }
+    let span = WORLD_SIZE as f64 / tiles as f64;
+    let scale = span / TILE_SIZE as f64; // world units per pixel
+    let origin = (tile.0 as f64 * span, tile.1 as f64 * span);
+    let explored: HashMap<(i32, i32), &Sector> = sectors.iter().map(|s| ((s.x, s.y), s)).collect();
+    let mut image = RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, FOG);
+    for py in 0..TILE_SIZE {
+        for px in 0..TILE_SIZE {
+            let x = (origin.0 + (px as f64 + 0.5) * scale) as i32;
+            let y = (origin.1 + (py as f64 + 0.5) * scale) as i32;
+            let sector = match explored.get(&(x / 100, y / 100)) {
+                Some(sector) => sector,
+                None => continue,
+            };
+            let mut color = map.get_color(x, y);
+            let hazard = sector.hazard.clamp(0, 100) as f64 / 200.0;
+            let stale = sector
+                .last_visited
+                .map(|visited| now - visited > Duration::days(STALE_DAYS))
+                .unwrap_or(true);
+            for i in 0..3 {
+                let mut channel = color[i] as f64 * (1.0 - hazard) + HAZARD[i] as f64 * hazard;
+                if stale {
+                    channel *= 0.7;
+                }
+                color[i] = channel as u8;
+            }
+            image.put_pixel(px, py, Rgba([color[0], color[1], color[2], 255]));
+        }
+    }
+    let marker = 1 + zoom as i32;
+    for location in locations {
+        if !explored.contains_key(&(location.x / 100, location.y / 100)) {
+            continue;
+        }
+        let cx = ((location.x as f64 - origin.0) / scale) as i32;
+        let cy = ((location.y as f64 - origin.1) / scale) as i32;
+        for y in cy - marker..=cy + marker {
+            for x in cx - marker..=cx + marker {
+                if x >= 0 && y >= 0 && x < TILE_SIZE as i32 && y < TILE_SIZE as i32 {
+                    image.put_pixel(x as u32, y as u32, LOCATION);
+                }
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::WORLD_MAP, terrain::DEFAULT_MAP};
+
+    #[test]
+    fn unexplored_sectors_are_hidden() {
+        let sectors = vec![Sector {
+            x: 0,
+            y: 0,
+            hazard: 0,
+            last_visited: Some(Utc::now()),
+        }];
+        let tile = render_tile(&DEFAULT_MAP, 0, (0, 0), &sectors, &[], Utc::now())
+            .unwrap()
+            .to_rgba8();
+        // Sector A1 covers the first 100 of 2600 world units
+        let scale = WORLD_MAP.width() as f64 / TILE_SIZE as f64;
+        let source = WORLD_MAP.to_rgb8();
+        let pixel = source.get_pixel((0.5 * scale) as u32, (0.5 * scale) as u32);
+        assert_eq!(&pixel.0, &tile.get_pixel(0, 0).0[..3]);
+        assert_eq!(FOG, *tile.get_pixel(TILE_SIZE - 1, TILE_SIZE - 1));
+    }
+
+    #[test]
+    fn rejects_tiles_outside_world() {
+        assert!(render_tile(&DEFAULT_MAP, 1, (2, 0), &[], &[], Utc::now()).is_err());
+        assert!(render_tile(&DEFAULT_MAP, MAX_ZOOM + 1, (0, 0), &[], &[], Utc::now()).is_err());
+    }
+}
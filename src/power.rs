@@ -0,0 +1,213 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use sqlx::PgPool;
+
+use crate::{
+    construction,
+    data::{FacilityType, RoomEffect, FACILITY_TYPES, ITEM_TYPES},
+    db::{
+        bunkers::{self, Bunker, BunkerData, PowerGridStatus},
+        messages,
+    },
+    error, reactor,
+};
+
+const MAX_OUTPUT: f64 = 120.0; // kW
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerPriorityRequest {
+    priorities: Vec<String>,
+    #[serde(default)]
+    limits: HashMap<String, i32>,
+}
+
+/// Facilities that draw power in their default order.
+pub fn get_consumers() -> Vec<&'static FacilityType> {
+    let mut consumers: Vec<&FacilityType> = FACILITY_TYPES
+        .values()
+        .filter(|t| t.power_draw > 0)
+        .collect();
+    consumers.sort_by_key(|t| (t.power_priority, &t.id));
+    consumers
+}
+
+/// Reactor output drops as the reactivity of the fuel rod is used up and as maintenance is
+/// neglected. Without fuel the bunker runs on backup power.
+pub fn get_output(data: &BunkerData) -> i32 {
+    let reactor = &data.reactor;
+    let facility = match data.facilities.get(reactor::REACTOR) {
+        Some(facility) if !facility.destroyed => facility,
+        _ => return 0,
+    };
+    let max_reactivity = ITEM_TYPES
+        .values()
+        .map(|t| t.reactivity)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let fuel_factor = if reactor.fuel < 1 {
+        0.4
+    } else {
+        0.85 + 0.15 * reactor.fuel.min(max_reactivity) as f64 / max_reactivity as f64
+    };
+    let maintenance_factor = 0.6 + 0.4 * facility.maintenance.clamp(0, 100) as f64 / 100.0;
+    let mut output = MAX_OUTPUT * fuel_factor * maintenance_factor;
+    if facility.malfunction {
+        output /= 2.0;
+    }
+    // Auxiliary reactors share the fuel supply of the main reactor
+    output += construction::get_capacity(data, RoomEffect::Power) as f64 * fuel_factor;
+    output as i32
+}
+
+/// Power supplied to a consumer in percent of its demand.
+pub fn get_supply(grid: &PowerGridStatus, consumer: &str) -> i32 {
+    grid.supply.get(consumer).copied().unwrap_or(100)
+}
+
+/// Distributes the output between consumers in order of priority. Consumers missing from the
+/// priority list come last.
+pub fn distribute(grid: &mut PowerGridStatus, output: i32) {
+    let mut remaining = output;
+    grid.output = output;
+    grid.supply.clear();
+    let consumers = get_consumers();
+    let prioritized = grid
+        .priorities
+        .iter()
+        .filter_map(|id| consumers.iter().find(|c| c.id == *id));
+    for consumer in prioritized.chain(consumers.iter()).unique_by(|c| &c.id) {
+        let demand = consumer.power_draw;
+        let limit = grid.limits.get(&consumer.id).copied().unwrap_or(demand);
+        let allocated = demand.min(limit).min(remaining).max(0);
+        remaining -= allocated;
+        grid.supply
+            .insert(consumer.id.clone(), allocated * 100 / demand);
+    }
+    grid.brownout = grid.supply.values().any(|supply| *supply < 100);
+}
+
+pub async fn handle_tick(pool: &PgPool, bunker: &mut Bunker) -> Result<(), error::Error> {
+    let output = get_output(&bunker.data);
+    let grid = &mut bunker.data.power;
+    let existing_brownout = grid.brownout;
+    distribute(grid, output);
+    if grid.brownout && !existing_brownout {
+        let affected = get_consumers()
+            .iter()
+            .filter(|c| get_supply(grid, &c.id) < 100)
+            .map(|c| c.name.to_lowercase())
+            .join(", ");
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Power grid"),
+                subject: format!("Brownout"),
+                body: format!(
+                    "The reactor output of {} kW is not enough to meet demand. \
+                    The following facilities are running on reduced power: {}.",
+                    grid.output, affected
+                ),
+            },
+        )
+        .await?;
+    } else if !grid.brownout && existing_brownout {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Power grid"),
+                subject: format!("Power restored"),
+                body: format!("All facilities are receiving full power again."),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn set_priorities(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &PowerPriorityRequest,
+) -> Result<(), error::Error> {
+    let consumers = get_consumers();
+    if request.priorities.iter().unique().count() != request.priorities.len()
+        || request
+            .priorities
+            .iter()
+            .any(|id| !consumers.iter().any(|c| c.id == *id))
+    {
+        Err(error::client_error("INVALID_PRIORITIES"))?;
+    }
+    if request.limits.values().any(|limit| *limit < 0) {
+        Err(error::client_error("INVALID_LIMIT"))?;
+    }
+    let grid = &mut bunker.data.power;
+    grid.priorities = request.priorities.clone();
+    grid.limits = request.limits.clone();
+    let output = grid.output;
+    distribute(grid, output);
+    bunkers::update_bunker_data_query(bunker)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::bunkers::FacilityStatus;
+
+    #[test]
+    fn brownout_hits_lowest_priority_first() {
+        let mut grid = PowerGridStatus {
+            priorities: vec![format!("cafeteria"), format!("air-recycling")],
+            ..Default::default()
+        };
+        distribute(&mut grid, 60);
+        assert!(grid.brownout);
+        assert_eq!(100, get_supply(&grid, "cafeteria"));
+        assert_eq!(100, get_supply(&grid, "air-recycling"));
+        assert_eq!(50, get_supply(&grid, "water-treatment"));
+        assert_eq!(0, get_supply(&grid, "horticulture"));
+    }
+
+    #[test]
+    fn output_follows_fuel_reactivity() {
+        let mut data = BunkerData::default();
+        data.facilities.insert(
+            reactor::REACTOR.to_owned(),
+            FacilityStatus {
+                maintenance: 100,
+                ..Default::default()
+            },
+        );
+        data.reactor.fuel = 10000;
+        let fresh = get_output(&data);
+        data.reactor.fuel = 1000;
+        let spent = get_output(&data);
+        data.reactor.fuel = 0;
+        let empty = get_output(&data);
+        assert!(fresh > spent && spent > empty);
+    }
+
+    #[test]
+    fn limits_leave_power_for_others() {
+        let mut grid = PowerGridStatus {
+            limits: HashMap::from([(format!("water-treatment"), 15)]),
+            ..Default::default()
+        };
+        distribute(&mut grid, 85);
+        assert_eq!(50, get_supply(&grid, "water-treatment"));
+        assert_eq!(100, get_supply(&grid, "cafeteria"));
+    }
+}
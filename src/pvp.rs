@@ -0,0 +1,235 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    battle::{self, BattleResult},
+    data::get_item_type,
+    db::{
+        battles,
+        bunkers::{self, Bunker},
+        expeditions::Expedition,
+        inhabitants::{self, Assignment, Inhabitant, SkillType},
+        items, locations, messages,
+        worlds::WorldTime,
+    },
+    error, expedition, raid,
+    util::skill_roll,
+};
+
+/// Days that a new bunker is safe from other players.
+pub const PROTECTION_DAYS: i64 = 7;
+/// Days that a raided bunker is safe from other players.
+const RAID_PROTECTION_DAYS: i64 = 2;
+/// Raiders can take at most this share of each stack of items.
+const LOOT_CAP: f64 = 0.1;
+
+async fn get_target(
+    pool: &PgPool,
+    location_id: i32,
+    report_body: &mut String,
+) -> Result<Option<Bunker>, error::Error> {
+    let location = locations::get_location(pool, location_id).await?;
+    let target = match location.data.bunker_id {
+        Some(bunker_id) => bunkers::get_bunker(pool, bunker_id).await?,
+        None => None,
+    };
+    if target.is_none() {
+        report_body.push_str(&format!("{} appears to be abandoned\n", location.name));
+    }
+    Ok(target)
+}
+
+fn is_guard(inhabitant: &Inhabitant) -> bool {
+    inhabitant.is_ready() && inhabitant.data.assignment == Some(Assignment::Security)
+}
+
+fn is_protected(bunker: &Bunker) -> bool {
+    bunker
+        .data
+        .security
+        .protected_until
+        .map(|protected_until| protected_until > Utc::now())
+        .unwrap_or(false)
+}
+
+pub async fn scout(
+    pool: &PgPool,
+    expedition: &Expedition,
+    location_id: i32,
+    team: &mut Vec<Inhabitant>,
+    report_body: &mut String,
+) -> Result<(), error::Error> {
+    let target = match get_target(pool, location_id, report_body).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    let inhabitants = inhabitants::get_inhabitants(pool, target.id).await?;
+    let stock = items::get_items(pool, target.id).await?;
+    let guards = inhabitants.iter().filter(|i| is_guard(i)).count();
+    let defense = raid::get_defense(&stock);
+    report_body.push_str(&format!("Scouted Bunker {}\n", target.number));
+    report_body.push_str(&format!(
+        "Estimated population: {}\n",
+        (inhabitants.len() + 2) / 5 * 5
+    ));
+    report_body.push_str(&format!("Guards on duty: {}\n", guards));
+    report_body.push_str(&format!(
+        "Fortifications: {}\n",
+        match defense {
+            0 => "none",
+            1..=20 => "weak",
+            21..=60 => "moderate",
+            _ => "strong",
+        }
+    ));
+    if is_protected(&target) {
+        report_body.push_str("The bunker is under protection and can't be raided yet\n");
+    }
+    let stealth_sum: i32 = team
+        .iter()
+        .map(|i| i.get_skill_level(SkillType::Stealth))
+        .sum();
+    let stealth_avg = (stealth_sum as f64 / team.len() as f64).ceil() as i32;
+    if skill_roll(0.2, stealth_avg) {
+        for member in team.iter_mut() {
+            member.add_xp(SkillType::Stealth, 30);
+        }
+    } else {
+        report_body.push_str("The team was spotted by the bunker's guards\n");
+        let sender = bunkers::get_bunker(pool, expedition.bunker_id).await?;
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: target.id,
+                sender_name: format!("Security team"),
+                subject: format!("Scouts spotted"),
+                body: match sender {
+                    Some(sender) => format!(
+                        "A scouting party from Bunker {} was spotted near the bunker.",
+                        sender.number
+                    ),
+                    None => format!("A scouting party was spotted near the bunker."),
+                },
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Attacks the bunker at the location. Returns true if the team had to retreat.
+pub async fn raid(
+    pool: &PgPool,
+    world: &WorldTime,
+    expedition: &Expedition,
+    location_id: i32,
+    team: &mut Vec<Inhabitant>,
+    found: &mut HashMap<String, i32>,
+    report_body: &mut String,
+) -> Result<bool, error::Error> {
+    let target = match get_target(pool, location_id, report_body).await? {
+        Some(target) => target,
+        None => return Ok(false),
+    };
+    if is_protected(&target) {
+        report_body.push_str(&format!(
+            "Bunker {} is under protection and can't be raided yet\n",
+            target.number
+        ));
+        return Ok(false);
+    }
+    let attacker_name = match bunkers::get_bunker(pool, expedition.bunker_id).await? {
+        Some(attacker) => format!("Bunker {}", attacker.number),
+        None => format!("another bunker"),
+    };
+    let mut guards: Vec<Inhabitant> = inhabitants::get_inhabitants(pool, target.id)
+        .await?
+        .into_iter()
+        .filter(is_guard)
+        .collect();
+    let stock = items::get_items(pool, target.id).await?;
+    let issued_ammo = raid::arm_guards(&mut guards, &stock);
+    let defense = raid::get_defense(&stock);
+    let log = battle::skirmish(team, &mut guards, expedition.data.tactic, defense)?;
+    battles::create_battle(pool, expedition.bunker_id, Some(expedition.id), &log).await?;
+    battles::create_battle(pool, target.id, None, &log).await?;
+    report_body.push_str(&format!("Attacked Bunker {}\n", target.number));
+    report_body.push_str(&log.to_report());
+    let mut target_report = format!(
+        "The bunker was attacked by a team from {}.\n",
+        attacker_name
+    );
+    target_report.push_str(&log.to_report());
+    let victory = log.result == BattleResult::Victory;
+    for guard in guards.iter_mut() {
+        if let Some((ammo_type, issued)) = issued_ammo.get(&guard.id) {
+            let used = issued - guard.data.ammo;
+            if used > 0 {
+                items::remove_item(pool, target.id, ammo_type, used).await?;
+            }
+        }
+        guard.data.weapon_type = None;
+        guard.data.ammo = 0;
+        guard.add_xp(SkillType::Combat, if victory { 20 } else { 50 });
+        inhabitants::update_inhabitant_data(pool, guard).await?;
+    }
+    if victory {
+        let available: HashMap<String, i32> = stock
+            .iter()
+            .filter(|item| get_item_type(&item.item_type).defense == 0)
+            .map(|item| {
+                (
+                    item.item_type.clone(),
+                    (item.quantity as f64 * LOOT_CAP) as i32,
+                )
+            })
+            .filter(|(_, quantity)| *quantity > 0)
+            .collect();
+        let (max_weight, max_volume) = expedition::get_carrying_capacity(team);
+        let (taken, _) = expedition::pack_loot(&available, max_weight, max_volume);
+        for (item_type_id, quantity) in taken {
+            items::remove_item(pool, target.id, &item_type_id, quantity).await?;
+            let item_type = get_item_type(&item_type_id);
+            let line = if quantity == 1 {
+                format!("{}\n", item_type.name)
+            } else {
+                format!("{} ({})\n", item_type.name_plural, quantity)
+            };
+            report_body.push_str(&format!("Took {}", line));
+            target_report.push_str(&format!("Stolen: {}", line));
+            *found.entry(item_type_id).or_insert(0) += quantity;
+        }
+        items::remove_empty_items(pool, target.id).await?;
+    }
+    let protected_until =
+        Utc::now() + Duration::days(RAID_PROTECTION_DAYS) / world.time_acceleration;
+    bunkers::set_security_field(
+        pool,
+        target.id,
+        "protectedUntil",
+        serde_json::json!(protected_until),
+    )
+    .await?;
+    messages::create_system_message(
+        pool,
+        &messages::NewSystemMessage {
+            receiver_bunker_id: target.id,
+            sender_name: format!("Security team"),
+            subject: if victory {
+                format!("Bunker raided by {}", attacker_name)
+            } else {
+                format!("Raid by {} repelled", attacker_name)
+            },
+            body: target_report,
+        },
+    )
+    .await?;
+    Ok(!victory)
+}
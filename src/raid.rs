@@ -0,0 +1,276 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use rand::{seq::SliceRandom, Rng};
+use sqlx::PgPool;
+
+use crate::{
+    battle::{self, BattleResult},
+    data::{get_item_type, ITEM_TYPES},
+    db::{
+        battles,
+        bunkers::Bunker,
+        inhabitants::{Assignment, Inhabitant, SkillType},
+        items::{self, Item},
+        messages,
+        worlds::WorldTime,
+    },
+    error, facility, terrain,
+    util::roll_dice,
+};
+
+const RAID_COOLDOWN_DAYS: i64 = 7;
+const MAX_GUARD_AMMO: i32 = 20;
+
+pub async fn handle_tick(
+    pool: &PgPool,
+    world: &WorldTime,
+    bunker: &mut Bunker,
+    inhabitants: &mut Vec<Inhabitant>,
+) -> Result<(), error::Error> {
+    let now = Utc::now();
+    let cooldown = Duration::days(RAID_COOLDOWN_DAYS) / world.time_acceleration;
+    if let Some(last_raid) = bunker.data.security.last_raid {
+        if now < last_raid + cooldown {
+            return Ok(());
+        }
+    }
+    bunker
+        .data
+        .security
+        .expeditions
+        .retain(|departure| *departure > now - cooldown);
+    let visibility = get_visibility(pool, bunker, inhabitants, now - cooldown).await?;
+    if visibility < 1 || !roll_dice(visibility as f64 * 0.0002, 1) {
+        return Ok(());
+    }
+    bunker.data.security.last_raid = Some(now);
+    let quantity = 1 + visibility / 10 + rand::thread_rng().gen_range(0..3);
+    let stock = items::get_items(pool, bunker.id).await?;
+    let defense = get_defense(&stock);
+    let (mut guards, others): (Vec<Inhabitant>, Vec<Inhabitant>) = inhabitants
+        .drain(..)
+        .partition(|i| i.is_ready() && i.data.assignment == Some(Assignment::Security));
+    inhabitants.extend(others);
+    let issued_ammo = arm_guards(&mut guards, &stock);
+    let mut report_body = String::new();
+    let repelled = if guards.is_empty() {
+        report_body.push_str(&format!(
+            "The bunker was attacked by {} raiders. There were no guards on duty.\n",
+            quantity
+        ));
+        false
+    } else {
+        let map = terrain::get_world_map(pool, world.id).await?;
+        let terrain = map.get_terrain(bunker.x, bunker.y);
+        let log = battle::raid(&mut guards, quantity, &terrain.id, defense)?;
+        battles::create_battle(pool, bunker.id, None, &log).await?;
+        report_body.push_str(&log.to_report());
+        for (item_type_id, quantity) in &log.loot {
+            items::add_item(pool, bunker.id, item_type_id, *quantity).await?;
+            let item_type = get_item_type(item_type_id);
+            if *quantity == 1 {
+                report_body.push_str(&format!("Recovered {}\n", item_type.name));
+            } else {
+                report_body.push_str(&format!(
+                    "Recovered {} ({})\n",
+                    item_type.name_plural, quantity
+                ));
+            }
+        }
+        log.result == BattleResult::Victory
+    };
+    for guard in guards.iter_mut() {
+        if let Some((ammo_type, issued)) = issued_ammo.get(&guard.id) {
+            let used = issued - guard.data.ammo;
+            if used > 0 {
+                items::remove_item(pool, bunker.id, ammo_type, used).await?;
+            }
+        }
+        guard.data.weapon_type = None;
+        guard.data.ammo = 0;
+        guard.add_xp(SkillType::Combat, if repelled { 50 } else { 20 });
+    }
+    inhabitants.extend(guards);
+    if !repelled {
+        // The guards may have used up some of the stock
+        let stock = items::get_items(pool, bunker.id).await?;
+        steal(pool, bunker, &stock, quantity, &mut report_body).await?;
+        damage_facilities(bunker, &mut report_body);
+        wound(inhabitants, quantity, &mut report_body);
+    }
+    items::remove_empty_items(pool, bunker.id).await?;
+    messages::create_system_message(
+        pool,
+        &messages::NewSystemMessage {
+            receiver_bunker_id: bunker.id,
+            sender_name: format!("Security team"),
+            subject: if repelled {
+                format!("Raid repelled")
+            } else {
+                format!("Bunker raided")
+            },
+            body: report_body,
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Larger and more active bunkers are more likely to attract raiders.
+async fn get_visibility(
+    pool: &PgPool,
+    bunker: &Bunker,
+    inhabitants: &[Inhabitant],
+    since: chrono::DateTime<Utc>,
+) -> Result<i32, error::Error> {
+    let security = &bunker.data.security;
+    let expeditions = security.expeditions.iter().filter(|t| **t > since).count() as i32;
+    let battles = battles::count_battles_since(pool, bunker.id, since).await?;
+    // Radio broadcasts can be traced back to the bunker
+    let broadcasting = match security.last_broadcast {
+        Some(last_broadcast) if last_broadcast > since => 10,
+        _ => 0,
+    };
+    Ok(inhabitants.len() as i32 + expeditions * 5 + battles * 2 + broadcasting)
+}
+
+/// Total fortification value of the defenses in stock.
+pub fn get_defense(stock: &[Item]) -> i32 {
+    stock
+        .iter()
+        .map(|item| get_item_type(&item.item_type).defense * item.quantity)
+        .sum()
+}
+
+/// Hands out the best weapons in stock to the guards. Returns the ammunition issued to each guard.
+pub fn arm_guards(guards: &mut [Inhabitant], stock: &[Item]) -> HashMap<i32, (String, i32)> {
+    let mut available: HashMap<String, i32> = stock
+        .iter()
+        .map(|item| (item.item_type.clone(), item.quantity))
+        .collect();
+    let mut weapons: Vec<_> = stock
+        .iter()
+        .filter_map(|item| ITEM_TYPES.get(&item.item_type))
+        .filter(|item_type| item_type.weapon)
+        .collect();
+    weapons.sort_by_key(|weapon| -weapon.damage);
+    let mut issued = HashMap::new();
+    for guard in guards.iter_mut() {
+        guard.data.weapon_type = None;
+        guard.data.ammo = 0;
+        for weapon in &weapons {
+            let ammo = match &weapon.ammo_type {
+                Some(ammo_type) => available.get(ammo_type).copied().unwrap_or(0),
+                None => 0,
+            };
+            if available.get(&weapon.id).copied().unwrap_or(0) < 1
+                || (weapon.ammo_type.is_some() && ammo < 1)
+            {
+                continue;
+            }
+            *available.entry(weapon.id.clone()).or_insert(0) -= 1;
+            guard.data.weapon_type = Some(weapon.id.clone());
+            if let Some(ammo_type) = &weapon.ammo_type {
+                guard.data.ammo = ammo.min(MAX_GUARD_AMMO);
+                *available.entry(ammo_type.clone()).or_insert(0) -= guard.data.ammo;
+                issued.insert(guard.id, (ammo_type.clone(), guard.data.ammo));
+            }
+            break;
+        }
+    }
+    issued
+}
+
+async fn steal(
+    pool: &PgPool,
+    bunker: &Bunker,
+    stock: &[Item],
+    raiders: i32,
+    report_body: &mut String,
+) -> Result<(), error::Error> {
+    let mut rng = rand::thread_rng();
+    let mut stolen: HashMap<&str, i32> = HashMap::new();
+    for _ in 0..raiders {
+        if let Some(item) = stock
+            .iter()
+            .filter(|item| {
+                item.quantity > *stolen.get(item.item_type.as_str()).unwrap_or(&0)
+                    && get_item_type(&item.item_type).defense == 0
+            })
+            .collect::<Vec<_>>()
+            .choose(&mut rng)
+        {
+            let remaining = item.quantity - stolen.get(item.item_type.as_str()).unwrap_or(&0);
+            let quantity = rng.gen_range(1..=(remaining / 4).max(1));
+            *stolen.entry(&item.item_type).or_insert(0) += quantity;
+        }
+    }
+    for (item_type_id, quantity) in stolen {
+        if !items::remove_item(pool, bunker.id, item_type_id, quantity).await? {
+            continue;
+        }
+        let item_type = get_item_type(item_type_id);
+        if quantity == 1 {
+            report_body.push_str(&format!("Stolen: {}\n", item_type.name));
+        } else {
+            report_body.push_str(&format!(
+                "Stolen: {} ({})\n",
+                item_type.name_plural, quantity
+            ));
+        }
+    }
+    if let Some(item) = stock
+        .iter()
+        .filter(|item| get_item_type(&item.item_type).defense > 0)
+        .collect::<Vec<_>>()
+        .choose(&mut rng)
+    {
+        if items::remove_item(pool, bunker.id, &item.item_type, 1).await? {
+            report_body.push_str(&format!(
+                "A {} was destroyed\n",
+                get_item_type(&item.item_type).name.to_lowercase()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn damage_facilities(bunker: &mut Bunker, report_body: &mut String) {
+    let mut rng = rand::thread_rng();
+    for facility_type in facility::get_facility_types() {
+        let status = match bunker.data.facilities.get_mut(&facility_type.id) {
+            Some(status) if facility_type.decay > 0 && !status.destroyed => status,
+            _ => continue,
+        };
+        let damage = rng.gen_range(0..25);
+        if damage > 10 {
+            status.maintenance = (status.maintenance - damage).max(0);
+            report_body.push_str(&format!(
+                "The raiders damaged the {}\n",
+                facility_type.name.to_lowercase()
+            ));
+        }
+    }
+}
+
+fn wound(inhabitants: &mut [Inhabitant], raiders: i32, report_body: &mut String) {
+    let mut rng = rand::thread_rng();
+    let count = rng.gen_range(0..=raiders.min(3));
+    let mut victims: Vec<_> = inhabitants
+        .iter_mut()
+        .filter(|i| i.expedition_id.is_none() && !i.data.wounded)
+        .collect();
+    victims.shuffle(&mut rng);
+    for victim in victims.into_iter().take(count as usize) {
+        victim.data.wounded = true;
+        victim.data.health -= rng.gen_range(10..30);
+        victim.changed = true;
+        report_body.push_str(&format!("{} was wounded\n", victim.name));
+    }
+}
@@ -0,0 +1,177 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+use crate::{
+    cafeteria::PORTION_CALORIES,
+    data::ITEM_TYPES,
+    db::{
+        bunkers::{self, Bunker, RationGroup, Rationing},
+        inhabitants::{self, get_age, Inhabitant},
+        items,
+    },
+    error,
+};
+
+/// Portions eaten per day on full rations.
+const PORTIONS_PER_DAY: f64 = 2.0;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RationingRequest {
+    rationing: Rationing,
+    priority_groups: Vec<RationGroup>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FoodProjection {
+    pub stock: i32,        // portions, including uncooked food
+    pub production: f64,   // portions per day from crops
+    pub consumption: f64,  // portions per day
+    pub days: Option<f64>, // none if production covers consumption
+}
+
+impl Rationing {
+    /// Share of a full portion served per meal.
+    pub fn portion_size(self) -> f64 {
+        match self {
+            Rationing::Full => 1.0,
+            Rationing::Reduced => 0.75,
+            Rationing::Survival => 0.5,
+        }
+    }
+}
+
+impl RationGroup {
+    pub fn contains(self, inhabitant: &Inhabitant, now: NaiveDateTime) -> bool {
+        let data = &inhabitant.data;
+        match self {
+            RationGroup::Children => get_age(now, inhabitant.date_of_birth) < 16,
+            RationGroup::Workers => data.assignment.is_some(),
+            RationGroup::Sick => {
+                data.sick || data.wounded || data.infection || data.bleeding || data.recovering
+            }
+        }
+    }
+}
+
+/// Position of the first priority group the inhabitant belongs to, or the number of groups if
+/// the inhabitant isn't prioritized.
+pub fn get_priority(groups: &[RationGroup], inhabitant: &Inhabitant, now: NaiveDateTime) -> usize {
+    groups
+        .iter()
+        .position(|group| group.contains(inhabitant, now))
+        .unwrap_or(groups.len())
+}
+
+/// Share of a full portion the inhabitant is served per meal.
+pub fn get_portion_size(bunker: &Bunker, inhabitant: &Inhabitant, now: NaiveDateTime) -> f64 {
+    let cafeteria = &bunker.data.cafeteria;
+    if get_priority(&cafeteria.priority_groups, inhabitant, now) < cafeteria.priority_groups.len() {
+        1.0
+    } else {
+        cafeteria.rationing.portion_size()
+    }
+}
+
+pub async fn get_food_projection(
+    pool: &PgPool,
+    bunker: &Bunker,
+    now: NaiveDateTime,
+) -> Result<FoodProjection, error::Error> {
+    let calories: i32 = items::get_items(pool, bunker.id)
+        .await?
+        .iter()
+        .filter_map(|item| {
+            ITEM_TYPES
+                .get(&item.item_type)
+                .filter(|item_type| item_type.food)
+                .map(|item_type| item_type.calories * item.quantity)
+        })
+        .sum();
+    let stock = bunker.data.cafeteria.food + calories / PORTION_CALORIES;
+    let production: f64 = bunker
+        .data
+        .horticulture
+        .crops
+        .iter()
+        .filter_map(|crop| {
+            let seed_type = ITEM_TYPES.get(&crop.seed_type)?;
+            let produce = ITEM_TYPES.get(seed_type.produce.as_ref()?)?;
+            Some(
+                (crop.quantity * produce.calories) as f64
+                    / seed_type.growth_time.max(1) as f64
+                    / PORTION_CALORIES as f64,
+            )
+        })
+        .sum();
+    let consumption: f64 = inhabitants::get_inhabitants(pool, bunker.id)
+        .await?
+        .iter()
+        .map(|inhabitant| PORTIONS_PER_DAY * get_portion_size(bunker, inhabitant, now))
+        .sum();
+    Ok(FoodProjection {
+        stock,
+        production,
+        consumption,
+        days: if consumption > production {
+            Some(stock as f64 / (consumption - production))
+        } else {
+            None
+        },
+    })
+}
+
+pub async fn set_rationing(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &RationingRequest,
+) -> Result<(), error::Error> {
+    let groups = &request.priority_groups;
+    if (1..groups.len()).any(|i| groups[..i].contains(&groups[i])) {
+        Err(error::client_error("INVALID_PRIORITIES"))?;
+    }
+    let cafeteria = &mut bunker.data.cafeteria;
+    cafeteria.rationing = request.rationing;
+    cafeteria.priority_groups = request.priority_groups.clone();
+    bunkers::update_bunker_data_query(bunker)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::db::inhabitants::{test_inhabitant, Assignment, InhabitantData};
+
+    #[test]
+    fn first_matching_group_decides_priority() {
+        let now = NaiveDate::from_ymd(2030, 1, 1).and_hms(0, 0, 0);
+        let mut child = test_inhabitant(
+            1,
+            InhabitantData {
+                sick: true,
+                ..Default::default()
+            },
+        );
+        child.date_of_birth = NaiveDate::from_ymd(2020, 1, 1);
+        let worker = test_inhabitant(
+            2,
+            InhabitantData {
+                assignment: Some(Assignment::Workshop),
+                ..Default::default()
+            },
+        );
+        let groups = [RationGroup::Sick, RationGroup::Children];
+        assert_eq!(0, get_priority(&groups, &child, now));
+        assert_eq!(2, get_priority(&groups, &worker, now));
+    }
+}
@@ -3,18 +3,19 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use rand::Rng;
+use actix::Addr;
 use sqlx::PgPool;
 
 use crate::{
+    broadcaster::{Broadcaster, BunkerMessage, Message, ReactorEvent, WorldMessage},
     data::ITEM_TYPES,
     db::{
-        bunkers::{self, Bunker},
-        inhabitants::{self, Assignment, Inhabitant, SkillType},
+        bunkers::{self, Bunker, Evacuation, FacilityStatus, ReactorStatus},
+        inhabitants::{Assignment, Inhabitant},
         items, messages,
     },
     error,
-    util::{roll_dice, skill_roll},
+    util::{get_sector, get_sector_name, roll_dice},
 };
 
 #[derive(serde::Deserialize)]
@@ -23,16 +24,44 @@ pub struct RefuelingRequest {
     item_type: String,
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvacuationRequest {
+    evacuation: Evacuation,
+}
+
+/// Below this maintenance level an unfixed malfunction can escalate to a radiation leak and then
+/// a meltdown.
+const LEAK_THRESHOLD: i32 = 30;
+pub const REACTOR: &str = "reactor"; // facility id
+const LEAK_EXPOSURE: i32 = 4;
+const MELTDOWN_EXPOSURE: i32 = 40;
+const FALLOUT_EXPOSURE: i32 = 2;
+const FALLOUT_TICKS: i32 = 300;
+const REBUILD_COMPONENTS: &[(&str, i32)] = &[("reactor-core", 1), ("steel", 20)];
+
 pub async fn handle_tick(
     pool: &PgPool,
+    broadcaster: &Addr<Broadcaster>,
     bunker: &mut Bunker,
     inhabitants: &mut Vec<Inhabitant>,
-) -> Result<i32, error::Error> {
-    let workers: Vec<_> = inhabitants
-        .iter_mut()
-        .filter(|i| i.is_ready() && i.data.assignment == Some(Assignment::Reactor))
-        .collect();
-    let status = &mut bunker.data.reactor;
+) -> Result<(), error::Error> {
+    let data = &mut *bunker.data;
+    let status = &mut data.reactor;
+    let facility = match data.facilities.get(REACTOR) {
+        Some(facility) => facility,
+        None => return Ok(()),
+    };
+    if facility.destroyed {
+        if status.fallout > 0 {
+            status.fallout -= 1;
+            irradiate(inhabitants, status.evacuation, FALLOUT_EXPOSURE);
+        }
+        return Ok(());
+    }
+    if status.leak {
+        irradiate(inhabitants, status.evacuation, LEAK_EXPOSURE);
+    }
     if status.fuel == 1 {
         messages::create_system_message(
             pool,
@@ -45,68 +74,216 @@ pub async fn handle_tick(
         ).await?;
     }
     status.fuel = (status.fuel - 1).max(0);
-    status.maintenance = (status.maintenance - 1).max(0);
-    let existing_malfunction = status.malfunction;
-    if !status.malfunction && roll_dice(0.01, 100 - status.maintenance) {
-        status.malfunction = true;
-    }
-    for mut inhabitant in workers {
-        if !status.malfunction && status.maintenance >= 100 {
-            break;
-        }
-        if inhabitant.data.sleeping {
-            if status.malfunction {
-                inhabitant.data.sleep_block = 2;
-                inhabitant.data.sleeping = false;
-            } else {
-                continue;
-            }
-        }
-        let level = inhabitants::get_inhabitant_skill_level(inhabitant, SkillType::Reactor);
-        if skill_roll(0.1, level) {
-            status.malfunction = false;
-            let improvement =
-                (rand::thread_rng().gen_range(1..3) + level).min(100 - status.maintenance);
-            status.maintenance += improvement;
-            inhabitants::add_xp_to_skill(&mut inhabitant, SkillType::Reactor, improvement * 10);
-            inhabitant.changed = true;
+    let existing_leak = status.leak;
+    match escalate(status.leak, facility, roll_dice) {
+        Escalation::Contained => status.leak = false,
+        Escalation::Leak => status.leak = true,
+        Escalation::Meltdown => {
+            melt_down(pool, broadcaster, bunker, inhabitants).await?;
+            return Ok(());
         }
+        Escalation::None => {}
     }
-    if existing_malfunction && !status.malfunction {
+    if existing_leak && !status.leak {
         messages::create_system_message(
             pool,
             &messages::NewSystemMessage {
                 receiver_bunker_id: bunker.id,
                 sender_name: format!("Reactor team"),
-                subject: format!("Reactor report"),
+                subject: format!("Radiation leak contained"),
                 body: format!(
-                    "The reactor malfunction has been fixed. Power output is back to normal."
+                    "The reactor has been repaired and the radiation leak has been contained."
                 ),
             },
         )
         .await?;
-    } else if !existing_malfunction && status.malfunction {
-        messages::create_system_message(
+    } else if !existing_leak && status.leak {
+        alert(
             pool,
-            &messages::NewSystemMessage {
-                receiver_bunker_id: bunker.id,
-                sender_name: format!("Reactor warning system"),
-                subject: format!("Reactor malfunction"),
-                body: format!(
-                    "A malfunction has been detected in the reactor. Power output is reduced."
-                ),
-            },
+            broadcaster,
+            bunker.id,
+            ReactorEvent::Leak,
+            "Radiation leak",
+            "The unrepaired reactor malfunction has caused a radiation leak. \
+            Everyone in the bunker is being exposed to radiation. \
+            Evacuate the reactor section to protect everyone but the reactor crew, \
+            or shelter everyone and leave the reactor unattended. \
+            If the malfunction isn't fixed, the reactor may melt down.",
         )
         .await?;
     }
-    let mut power_level = 100;
-    if status.fuel < 1 {
-        power_level /= 2;
+    Ok(())
+}
+
+#[derive(PartialEq, Debug)]
+enum Escalation {
+    None,
+    Contained,
+    Leak,
+    Meltdown,
+}
+
+/// Decides what happens to a leak this tick. `roll` is called with a per-point chance and the
+/// number of points by which the maintenance is below the leak threshold.
+fn escalate(leak: bool, facility: &FacilityStatus, roll: fn(f64, i32) -> bool) -> Escalation {
+    if !facility.malfunction {
+        return if leak {
+            Escalation::Contained
+        } else {
+            Escalation::None
+        };
+    }
+    if facility.maintenance >= LEAK_THRESHOLD {
+        return Escalation::None;
     }
-    if status.malfunction {
-        power_level /= 2;
+    let risk = LEAK_THRESHOLD - facility.maintenance;
+    if !leak && roll(0.002, risk) {
+        Escalation::Leak
+    } else if leak && roll(0.0005, risk) {
+        Escalation::Meltdown
+    } else {
+        Escalation::None
     }
-    Ok(power_level)
+}
+
+fn irradiate(inhabitants: &mut [Inhabitant], evacuation: Evacuation, exposure: i32) {
+    for inhabitant in inhabitants {
+        if inhabitant.expedition_id.is_some() {
+            continue;
+        }
+        let crew = inhabitant.data.assignment == Some(Assignment::Reactor);
+        inhabitant.data.surface_exposure += match evacuation {
+            Evacuation::None => exposure,
+            Evacuation::ReactorSection if crew => exposure,
+            _ => exposure / 4,
+        };
+        inhabitant.changed = true;
+    }
+}
+
+async fn melt_down(
+    pool: &PgPool,
+    broadcaster: &Addr<Broadcaster>,
+    bunker: &mut Bunker,
+    inhabitants: &mut [Inhabitant],
+) -> Result<(), error::Error> {
+    let data = &mut *bunker.data;
+    irradiate(inhabitants, data.reactor.evacuation, MELTDOWN_EXPOSURE);
+    data.reactor = ReactorStatus {
+        fallout: FALLOUT_TICKS,
+        evacuation: data.reactor.evacuation,
+        ..Default::default()
+    };
+    data.facilities.insert(
+        REACTOR.to_owned(),
+        FacilityStatus {
+            destroyed: true,
+            evacuated: data.reactor.evacuation == Evacuation::Shelter,
+            ..Default::default()
+        },
+    );
+    alert(
+        pool,
+        broadcaster,
+        bunker.id,
+        ReactorEvent::Meltdown,
+        "Reactor meltdown",
+        "The reactor has melted down and is destroyed, along with its fuel and spare parts. \
+        Fallout will keep exposing everyone in the bunker to radiation for a while. \
+        A new reactor can be built from a reactor core and steel.",
+    )
+    .await?;
+    broadcaster.do_send(WorldMessage {
+        world_id: bunker.world_id,
+        message: Message::Meltdown {
+            sector: get_sector_name(get_sector(bunker.x, bunker.y)),
+        },
+    });
+    Ok(())
+}
+
+async fn alert(
+    pool: &PgPool,
+    broadcaster: &Addr<Broadcaster>,
+    bunker_id: i32,
+    event: ReactorEvent,
+    subject: &str,
+    body: &str,
+) -> Result<(), error::Error> {
+    messages::create_system_message(
+        pool,
+        &messages::NewSystemMessage {
+            receiver_bunker_id: bunker_id,
+            sender_name: format!("Reactor warning system"),
+            subject: subject.to_owned(),
+            body: body.to_owned(),
+        },
+    )
+    .await?;
+    broadcaster.do_send(BunkerMessage {
+        bunker_id,
+        message: Message::ReactorEvent { event },
+    });
+    Ok(())
+}
+
+fn is_destroyed(bunker: &Bunker) -> bool {
+    bunker
+        .data
+        .facilities
+        .get(REACTOR)
+        .map(|f| f.destroyed)
+        .unwrap_or(false)
+}
+
+pub async fn set_evacuation(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &EvacuationRequest,
+) -> Result<(), error::Error> {
+    bunker.data.reactor.evacuation = request.evacuation;
+    if let Some(facility) = bunker.data.facilities.get_mut(REACTOR) {
+        facility.evacuated = request.evacuation == Evacuation::Shelter;
+    }
+    bunkers::update_bunker_data_query(bunker)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn rebuild(pool: &PgPool, bunker: &mut Bunker) -> Result<(), error::Error> {
+    if !is_destroyed(bunker) {
+        Err(error::client_error("REACTOR_NOT_DESTROYED"))?;
+    }
+    let mut tx = pool.begin().await?;
+    for (item_type, quantity) in REBUILD_COMPONENTS {
+        let affected = items::remove_items_query(bunker.id, item_type, *quantity)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if affected < 1 {
+            Err(error::client_error("MISSING_ITEM"))?;
+        }
+    }
+    let evacuation = bunker.data.reactor.evacuation;
+    bunker.data.reactor = ReactorStatus {
+        evacuation,
+        ..Default::default()
+    };
+    bunker.data.facilities.insert(
+        REACTOR.to_owned(),
+        FacilityStatus {
+            maintenance: 50,
+            evacuated: evacuation == Evacuation::Shelter,
+            ..Default::default()
+        },
+    );
+    bunkers::update_bunker_data_query(bunker)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    items::remove_empty_items(pool, bunker.id).await?;
+    Ok(())
 }
 
 pub async fn refuel(
@@ -114,6 +291,9 @@ pub async fn refuel(
     bunker: &mut Bunker,
     refueling_request: &RefuelingRequest,
 ) -> Result<(), error::Error> {
+    if is_destroyed(bunker) {
+        Err(error::client_error("REACTOR_DESTROYED"))?;
+    }
     let mut tx = pool.begin().await?;
     let item_type = ITEM_TYPES
         .get(&refueling_request.item_type)
@@ -163,3 +343,78 @@ pub async fn refuel(
     items::remove_empty_items(pool, bunker.id).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::inhabitants::{test_inhabitant, InhabitantData};
+
+    fn malfunction(maintenance: i32) -> FacilityStatus {
+        FacilityStatus {
+            malfunction: true,
+            maintenance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn leak_escalates_to_meltdown() {
+        assert_eq!(
+            Escalation::Leak,
+            escalate(false, &malfunction(10), |_, _| true)
+        );
+        assert_eq!(
+            Escalation::Meltdown,
+            escalate(true, &malfunction(10), |_, _| true)
+        );
+        assert_eq!(
+            Escalation::None,
+            escalate(true, &malfunction(10), |_, _| false)
+        );
+    }
+
+    #[test]
+    fn maintained_reactor_does_not_escalate() {
+        let facility = malfunction(LEAK_THRESHOLD);
+        assert_eq!(Escalation::None, escalate(false, &facility, |_, _| true));
+        assert_eq!(Escalation::None, escalate(true, &facility, |_, _| true));
+    }
+
+    #[test]
+    fn repair_contains_leak() {
+        let facility = FacilityStatus::default();
+        assert_eq!(
+            Escalation::Contained,
+            escalate(true, &facility, |_, _| true)
+        );
+        assert_eq!(Escalation::None, escalate(false, &facility, |_, _| true));
+    }
+
+    fn inhabitant(assignment: Option<Assignment>, expedition_id: Option<i32>) -> Inhabitant {
+        let mut inhabitant = test_inhabitant(
+            1,
+            InhabitantData {
+                assignment,
+                ..Default::default()
+            },
+        );
+        inhabitant.expedition_id = expedition_id;
+        inhabitant
+    }
+
+    #[test]
+    fn evacuation_limits_exposure() {
+        let exposure = |evacuation: Evacuation| {
+            let mut inhabitants = [
+                inhabitant(Some(Assignment::Reactor), None),
+                inhabitant(None, None),
+                inhabitant(None, Some(1)),
+            ];
+            irradiate(&mut inhabitants, evacuation, 8);
+            inhabitants.map(|i| i.data.surface_exposure)
+        };
+        assert_eq!([8, 8, 0], exposure(Evacuation::None));
+        assert_eq!([8, 2, 0], exposure(Evacuation::ReactorSection));
+        assert_eq!([2, 2, 0], exposure(Evacuation::Shelter));
+    }
+}
@@ -0,0 +1,86 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::NaiveDateTime;
+use rand::Rng;
+use sqlx::{PgPool, Row};
+
+use crate::{
+    construction,
+    data::{RoomEffect, LAST_NAMES},
+    db::{
+        bunkers::{self, Bunker},
+        inhabitants::{self, NewInhabitant},
+        recruits,
+    },
+    error, generate,
+    util::roll_dice,
+};
+
+/// Generates a survivor met on the surface.
+pub fn generate_survivor(world_time: NaiveDateTime) -> NewInhabitant {
+    let mut rng = rand::thread_rng();
+    let last_name = &LAST_NAMES[rng.gen_range(0..LAST_NAMES.len())];
+    let mut person = generate::generate_person(world_time, 16, 70, &vec![last_name]);
+    person.data.health = rng.gen_range(50..=100);
+    person.data.surface_exposure = rng.gen_range(0..=30);
+    person.data.hunger = rng.gen_range(12..=30);
+    if roll_dice(0.3, 1) {
+        person.data.sick = true;
+        person.data.contagious = roll_dice(0.5, 1);
+    }
+    person
+}
+
+/// Moves a pending recruit into the bunker, optionally in quarantine.
+pub async fn accept(
+    pool: &PgPool,
+    bunker: &Bunker,
+    recruit_id: i32,
+    quarantine: bool,
+) -> Result<i32, error::Error> {
+    let recruit = recruits::get_recruit(pool, bunker.id, recruit_id)
+        .await?
+        .ok_or_else(|| error::client_error("RECRUIT_NOT_FOUND"))?;
+    let mut tx = pool.begin().await?;
+    // Prevents concurrent requests from accepting more recruits than there is room for
+    let locked = bunkers::get_bunker_for_update(&mut tx, bunker.id).await?;
+    let population: i64 = inhabitants::get_inhabitant_count_query(bunker.id)
+        .fetch_one(&mut tx)
+        .await?
+        .try_get(0)?;
+    if population >= construction::get_capacity(&locked.data, RoomEffect::Population) as i64 {
+        Err(error::client_error("BUNKER_FULL"))?;
+    }
+    let affected = recruits::delete_recruit_query(recruit.id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    if affected < 1 {
+        Err(error::client_error("RECRUIT_NOT_FOUND"))?;
+    }
+    let mut data = recruit.data.0;
+    data.quarantined = quarantine;
+    let inhabitant_id = inhabitants::create_inhabitant_query(
+        bunker.id,
+        &NewInhabitant {
+            name: recruit.name,
+            date_of_birth: recruit.date_of_birth,
+            data,
+        },
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .try_get(0)?;
+    tx.commit().await?;
+    Ok(inhabitant_id)
+}
+
+pub async fn reject(pool: &PgPool, bunker_id: i32, recruit_id: i32) -> Result<(), error::Error> {
+    let recruit = recruits::get_recruit(pool, bunker_id, recruit_id)
+        .await?
+        .ok_or_else(|| error::client_error("RECRUIT_NOT_FOUND"))?;
+    recruits::delete_recruit(pool, recruit.id).await
+}
@@ -0,0 +1,138 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    data::FACILITY_TYPES,
+    db::bunkers::{Bunker, FacilityStatus},
+    error, storage,
+    util::roll_dice,
+};
+
+/// Number of successful repair rolls needed to improvise a fix for a severe malfunction without
+/// spare parts.
+pub const IMPROVISED_REPAIRS: i32 = 30;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatePartsRequest {
+    facility: String,
+    parts: i32,
+}
+
+impl FacilityStatus {
+    /// Starts a malfunction. Poorly maintained machines are more likely to suffer severe
+    /// malfunctions.
+    pub fn break_down(&mut self) {
+        self.malfunction = true;
+        self.severe = roll_dice(0.005, 100 - self.maintenance);
+        self.downtime = 0;
+    }
+
+    /// Applies a successful repair roll and returns the maintenance improvement. Regular
+    /// malfunctions can sometimes be fixed without parts, severe malfunctions need a part or
+    /// `IMPROVISED_REPAIRS` successful rolls.
+    pub fn repair(&mut self, level: i32) -> i32 {
+        let has_part = self.parts > 0;
+        if self.malfunction {
+            let fixed = if self.severe {
+                self.downtime += 1;
+                has_part || self.downtime >= IMPROVISED_REPAIRS
+            } else {
+                has_part || roll_dice(0.25, 1)
+            };
+            if !fixed {
+                return 0;
+            }
+            if has_part {
+                self.parts -= 1;
+            }
+            self.malfunction = false;
+            self.severe = false;
+            self.downtime = 0;
+        } else if has_part && roll_dice(0.1, 1) {
+            self.parts -= 1;
+        }
+        let mut improvement = rand::thread_rng().gen_range(1..3) + level;
+        if !has_part {
+            improvement = (improvement / 2).max(1);
+        }
+        improvement = improvement.min(100 - self.maintenance);
+        self.maintenance += improvement;
+        improvement
+    }
+}
+
+/// Appended to malfunction warnings.
+pub fn get_severity_note(severe: bool, part_name: &str) -> String {
+    if severe {
+        format!(
+            " The malfunction is severe and can't be fixed without a {}, \
+            unless the team spends a long time improvising repairs.",
+            part_name
+        )
+    } else {
+        String::new()
+    }
+}
+
+pub async fn update_parts(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    request: &UpdatePartsRequest,
+) -> Result<(), error::Error> {
+    let item_type = FACILITY_TYPES
+        .get(&request.facility)
+        .and_then(|f| f.parts_item.as_ref())
+        .ok_or_else(|| error::client_error("INVALID_FACILITY"))?;
+    storage::update_stock(
+        pool,
+        bunker,
+        item_type,
+        request.parts,
+        |data| {
+            data.facilities
+                .get(&request.facility)
+                .map(|status| status.parts)
+                .unwrap_or(0)
+        },
+        |data| {
+            data.facilities
+                .get_mut(&request.facility)
+                .ok_or_else(|| error::client_error("INVALID_FACILITY"))?
+                .parts = request.parts;
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severe_malfunction_needs_part_or_downtime() {
+        let mut status = FacilityStatus {
+            malfunction: true,
+            severe: true,
+            ..Default::default()
+        };
+        for _ in 1..IMPROVISED_REPAIRS {
+            assert_eq!(0, status.repair(1));
+        }
+        assert!(status.repair(1) > 0);
+        assert!(!status.malfunction);
+
+        status.break_down();
+        status.severe = true;
+        status.parts = 1;
+        assert!(status.repair(1) > 0);
+        assert!(!status.malfunction);
+        assert_eq!(0, status.parts);
+    }
+}
@@ -0,0 +1,278 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    construction,
+    data::{ItemType, RoomEffect, ITEM_TYPES},
+    db::{
+        bunkers::{self, Bunker, BunkerData, StorageStatus},
+        items::{self, Item},
+        messages,
+    },
+    error,
+};
+
+/// Share of the prepared meals in the cafeteria lost per day.
+const MEAL_SPOILAGE: f64 = 0.1;
+
+/// Chance of perishable items in cold storage aging during a tick.
+const COLD_AGING: f64 = 0.25;
+
+/// Ticks between spoilage reports, about a day.
+const REPORT_TICKS: i32 = 24;
+
+pub fn get_volume(items: &[Item]) -> f64 {
+    items
+        .iter()
+        .filter_map(|item| {
+            ITEM_TYPES
+                .get(&item.item_type)
+                .map(|item_type| item_type.volume * item.quantity as f64)
+        })
+        .sum()
+}
+
+/// Storage space left in litres.
+pub async fn get_free_space(pool: &PgPool, bunker: &Bunker) -> Result<f64, error::Error> {
+    let items = items::get_items(pool, bunker.id).await?;
+    let capacity = construction::get_capacity(&bunker.data, RoomEffect::Storage) as f64;
+    Ok(capacity - get_volume(&items))
+}
+
+/// Moves items between the inventory and a stock kept in the bunker data, such as the medicine in
+/// the infirmary, until the stock holds `quantity` items. The bunker is reloaded with its row
+/// locked so concurrent requests can't duplicate items. `set_stock` may reject the new quantity.
+pub async fn update_stock<G, S>(
+    pool: &PgPool,
+    bunker: &mut Bunker,
+    item_type: &str,
+    quantity: i32,
+    get_stock: G,
+    set_stock: S,
+) -> Result<(), error::Error>
+where
+    G: FnOnce(&BunkerData) -> i32,
+    S: FnOnce(&mut BunkerData) -> Result<(), error::Error>,
+{
+    if quantity < 0 {
+        Err(error::client_error("INVALID_QUANTITY"))?;
+    }
+    let mut tx = pool.begin().await?;
+    *bunker = bunkers::get_bunker_for_update(&mut tx, bunker.id).await?;
+    let existing = get_stock(&bunker.data);
+    if existing < quantity {
+        let affected = items::remove_items_query(bunker.id, item_type, quantity - existing)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if affected < 1 {
+            Err(error::client_error("MISSING_ITEM"))?;
+        }
+    } else if existing > quantity {
+        items::add_item_query(bunker.id, item_type, existing - quantity)
+            .execute(&mut tx)
+            .await?;
+    } else {
+        return Ok(());
+    }
+    set_stock(&mut bunker.data)?;
+    bunkers::update_bunker_data_query(bunker)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    items::remove_empty_items(pool, bunker.id).await?;
+    Ok(())
+}
+
+/// Perishable items that fit in cold storage, those that spoil the fastest first.
+fn get_cold_stored<'a>(data: &BunkerData, items: &'a [Item]) -> Vec<&'a str> {
+    let mut perishables: Vec<(&Item, &ItemType)> = items
+        .iter()
+        .filter_map(|item| ITEM_TYPES.get(&item.item_type).map(|t| (item, t)))
+        .filter(|(_, item_type)| item_type.shelf_life > 0)
+        .collect();
+    perishables.sort_by_key(|(_, item_type)| item_type.shelf_life);
+    let mut space = construction::get_capacity(data, RoomEffect::ColdStorage) as f64;
+    let mut cold_stored = vec![];
+    for (item, item_type) in perishables {
+        let volume = item_type.volume * item.quantity as f64;
+        if volume <= space {
+            space -= volume;
+            cold_stored.push(item.item_type.as_str());
+        }
+    }
+    cold_stored
+}
+
+/// Average age of a stack after `quantity - previous` fresh items have been added to it.
+fn blend_age(age: i32, previous: i32, quantity: i32) -> i32 {
+    if quantity > previous && quantity > 0 {
+        (age as i64 * previous.max(0) as i64 / quantity as i64) as i32
+    } else {
+        age
+    }
+}
+
+fn round_randomly<R: Rng>(rng: &mut R, amount: f64) -> i32 {
+    let whole = amount.floor();
+    whole as i32
+        + if rng.gen::<f64>() < amount - whole {
+            1
+        } else {
+            0
+        }
+}
+
+pub async fn handle_tick(pool: &PgPool, bunker: &mut Bunker) -> Result<(), error::Error> {
+    let items = items::get_items(pool, bunker.id).await?;
+    let capacity = construction::get_capacity(&bunker.data, RoomEffect::Storage) as f64;
+    let overflow = get_volume(&items) > capacity;
+    let cold_stored = get_cold_stored(&bunker.data, &items);
+    let mut rng = rand::thread_rng();
+    let mut any_spoiled = false;
+    let storage = &mut bunker.data.storage;
+    storage
+        .age
+        .retain(|id, _| items.iter().any(|item| item.item_type == *id));
+    storage
+        .stock
+        .retain(|id, _| items.iter().any(|item| item.item_type == *id));
+    for item in &items {
+        let item_type = match ITEM_TYPES.get(&item.item_type) {
+            Some(item_type) if item_type.shelf_life > 0 => item_type,
+            _ => continue,
+        };
+        let previous = storage.stock.get(&item_type.id).copied().unwrap_or(0);
+        let age = storage.age.entry(item_type.id.clone()).or_insert(0);
+        *age = blend_age(*age, previous, item.quantity);
+        if !cold_stored.contains(&item_type.id.as_str()) || rng.gen::<f64>() < COLD_AGING {
+            *age += 1;
+        }
+        let mut spoiled = 0;
+        if *age > item_type.shelf_life * 24 {
+            // Items piled up in the corridors spoil twice as fast
+            let rate = item_type.spoilage / 24.0 * if overflow { 2.0 } else { 1.0 };
+            spoiled = round_randomly(&mut rng, item.quantity as f64 * rate).min(item.quantity);
+            if spoiled > 0 && items::remove_item(pool, bunker.id, &item_type.id, spoiled).await? {
+                if spoiled >= item.quantity {
+                    storage.age.remove(&item_type.id);
+                }
+                *storage.spoiled.entry(item_type.id.clone()).or_insert(0) += spoiled;
+                any_spoiled = true;
+            } else {
+                spoiled = 0;
+            }
+        }
+        storage
+            .stock
+            .insert(item_type.id.clone(), item.quantity - spoiled);
+    }
+    let existing_overflow = storage.overflow;
+    storage.overflow = overflow;
+    let cafeteria = &mut bunker.data.cafeteria;
+    let spoiled_meals = round_randomly(&mut rng, cafeteria.food as f64 * MEAL_SPOILAGE / 24.0);
+    cafeteria.discard_portions(spoiled_meals);
+    if any_spoiled {
+        items::remove_empty_items(pool, bunker.id).await?;
+    }
+    let storage = &mut bunker.data.storage;
+    storage.spoiled_meals += spoiled_meals;
+    storage.report_ticks += 1;
+    if storage.report_ticks >= REPORT_TICKS {
+        report_spoilage(pool, bunker.id, storage).await?;
+    }
+    if overflow && !existing_overflow {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker.id,
+                sender_name: format!("Storage room"),
+                subject: format!("Storage full"),
+                body: format!(
+                    "The storage room is full and items are piling up in the corridors. \
+                    Build more storage rooms or get rid of some items."
+                ),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Sends one message listing everything that has spoiled since the last report.
+async fn report_spoilage(
+    pool: &PgPool,
+    bunker_id: i32,
+    storage: &mut StorageStatus,
+) -> Result<(), error::Error> {
+    let mut spoiled_items: Vec<(&ItemType, i32)> = storage
+        .spoiled
+        .iter()
+        .filter_map(|(id, quantity)| ITEM_TYPES.get(id).map(|t| (t, *quantity)))
+        .collect();
+    spoiled_items.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    let mut spoiled: Vec<String> = spoiled_items
+        .iter()
+        .map(|(item_type, quantity)| {
+            if *quantity == 1 {
+                format!("1 {}", item_type.name.to_lowercase())
+            } else {
+                format!("{} {}", quantity, item_type.name_plural.to_lowercase())
+            }
+        })
+        .collect();
+    if storage.spoiled_meals > 0 {
+        spoiled.push(format!("{} prepared meals", storage.spoiled_meals));
+    }
+    storage.spoiled.clear();
+    storage.spoiled_meals = 0;
+    storage.report_ticks = 0;
+    if !spoiled.is_empty() {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker_id,
+                sender_name: format!("Storage room"),
+                subject: format!("Spoiled food discarded"),
+                body: format!(
+                    "The following items have spoiled and were discarded during the last day: {}. \
+                    Cold storage and preserved foods keep longer.",
+                    spoiled.join(", ")
+                ),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fastest_spoiling_items_are_cold_stored_first() {
+        let mut data = BunkerData::default();
+        data.rooms.insert(format!("cold-storage"), 1);
+        let item = |item_type: &str, quantity| Item {
+            id: 0,
+            bunker_id: 0,
+            item_type: item_type.to_owned(),
+            quantity,
+        };
+        let items = vec![item("carrot", 100), item("lettuce", 60), item("steel", 10)];
+        assert_eq!(vec!["lettuce"], get_cold_stored(&data, &items));
+    }
+
+    #[test]
+    fn fresh_items_lower_the_age_of_a_stack() {
+        assert_eq!(50, blend_age(100, 10, 20));
+        assert_eq!(100, blend_age(100, 20, 10));
+        assert_eq!(0, blend_age(100, 0, 5));
+    }
+}
@@ -0,0 +1,266 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use image::{DynamicImage, GenericImageView, RgbImage};
+use sqlx::PgPool;
+
+use crate::{
+    data::{TerrainType, TERRAIN_TYPES, WORLD_MAP},
+    db::{expeditions::Waypoint, worlds},
+    error,
+    util::get_distance,
+};
+
+pub const WORLD_SIZE: i32 = 2600;
+
+lazy_static! {
+    pub static ref DEFAULT_MAP: Arc<TerrainMap> = Arc::new(TerrainMap::from_image(&WORLD_MAP));
+    static ref WORLD_MAPS: Mutex<HashMap<i32, Arc<TerrainMap>>> = Mutex::new(HashMap::new());
+    static ref MAX_SPEED: f64 = TERRAIN_TYPES
+        .values()
+        .filter(|t| !t.impassable)
+        .map(|t| t.speed)
+        .fold(0.0, f64::max);
+}
+
+pub struct Route {
+    pub waypoints: Vec<Waypoint>,
+    pub distance: i32,        // metres
+    pub travel_distance: i32, // metres at full speed
+    pub exposure: f64,        // average exposure factor
+}
+
+/// Terrain types of a world, classified from the pixels of its map.
+pub struct TerrainMap {
+    width: i32,
+    height: i32,
+    grid: Vec<&'static TerrainType>,
+    image: RgbImage,
+}
+
+/// Returns the terrain map of a world, which is either generated or the default map.
+pub async fn get_world_map(pool: &PgPool, world_id: i32) -> Result<Arc<TerrainMap>, error::Error> {
+    if let Some(map) = WORLD_MAPS.lock().unwrap().get(&world_id) {
+        return Ok(map.clone());
+    }
+    let map = match worlds::get_world_map_image(pool, world_id).await? {
+        Some(png) => {
+            let image = image::load_from_memory(&png)
+                .map_err(|_| error::internal_error("Invalid world map"))?;
+            Arc::new(TerrainMap::from_image(&image))
+        }
+        None => DEFAULT_MAP.clone(),
+    };
+    WORLD_MAPS.lock().unwrap().insert(world_id, map.clone());
+    Ok(map)
+}
+
+fn classify_image(image: &DynamicImage) -> Vec<&'static TerrainType> {
+    let width = image.width();
+    let height = image.height();
+    let mut grid = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel(x, y).0;
+            let terrain_type = TERRAIN_TYPES
+                .values()
+                .flat_map(|t| t.colors.iter().map(move |c| (t, c)))
+                .min_by_key(|(_, color)| {
+                    (0..3)
+                        .map(|i| (pixel[i] as i32 - color[i] as i32).pow(2))
+                        .sum::<i32>()
+                })
+                .map(|(t, _)| t)
+                .expect("No terrain types defined");
+            grid.push(terrain_type);
+        }
+    }
+    grid
+}
+
+impl TerrainMap {
+    pub fn from_image(image: &DynamicImage) -> TerrainMap {
+        TerrainMap {
+            width: image.width() as i32,
+            height: image.height() as i32,
+            grid: classify_image(image),
+            image: image.to_rgb8(),
+        }
+    }
+
+    fn to_map(&self, position: (i32, i32)) -> (i32, i32) {
+        (
+            (position.0 * self.width / WORLD_SIZE).clamp(0, self.width - 1),
+            (position.1 * self.height / WORLD_SIZE).clamp(0, self.height - 1),
+        )
+    }
+
+    fn to_world(&self, position: (i32, i32)) -> (i32, i32) {
+        (
+            position.0 * WORLD_SIZE / self.width + WORLD_SIZE / self.width / 2,
+            position.1 * WORLD_SIZE / self.height + WORLD_SIZE / self.height / 2,
+        )
+    }
+
+    pub fn get_terrain(&self, x: i32, y: i32) -> &'static TerrainType {
+        let (map_x, map_y) = self.to_map((x, y));
+        self.grid[(map_y * self.width + map_x) as usize]
+    }
+
+    /// Color of the map image at the position.
+    pub fn get_color(&self, x: i32, y: i32) -> [u8; 3] {
+        let (map_x, map_y) = self.to_map((x, y));
+        self.image.get_pixel(map_x as u32, map_y as u32).0
+    }
+
+    /// Whether bunkers and locations can be placed at the position.
+    pub fn is_habitable(&self, x: i32, y: i32) -> bool {
+        let terrain = self.get_terrain(x, y);
+        !terrain.impassable && !terrain.water
+    }
+
+    /// Finds the fastest route between two points in world coordinates using A* on the terrain
+    /// grid. Returns `None` if the destination can't be reached.
+    pub fn find_route(&self, from: (i32, i32), to: (i32, i32)) -> Option<Route> {
+        let (width, height) = (self.width, self.height);
+        let pixel_size = WORLD_SIZE as f64 / width as f64 * 10.0; // metres
+        let start = self.to_map(from);
+        let goal = self.to_map(to);
+        let index = |(x, y): (i32, i32)| (y * width + x) as usize;
+        if self.grid[index(start)].impassable || self.grid[index(goal)].impassable {
+            return None;
+        }
+        let heuristic = |position: (i32, i32)| {
+            get_distance(self.to_world(position), self.to_world(goal)) as f64 / *MAX_SPEED
+        };
+        let mut costs = vec![f64::INFINITY; (width * height) as usize];
+        let mut previous: Vec<Option<(i32, i32)>> = vec![None; (width * height) as usize];
+        let mut open = BinaryHeap::new();
+        costs[index(start)] = 0.0;
+        open.push(Reverse((0u64, start)));
+        while let Some(Reverse((_, current))) = open.pop() {
+            if current == goal {
+                break;
+            }
+            let current_cost = costs[index(current)];
+            let current_terrain = self.grid[index(current)];
+            for (dx, dy) in [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ] {
+                let next = (current.0 + dx, current.1 + dy);
+                if next.0 < 0 || next.1 < 0 || next.0 >= width || next.1 >= height {
+                    continue;
+                }
+                let next_terrain = self.grid[index(next)];
+                if next_terrain.impassable {
+                    continue;
+                }
+                let step = if dx != 0 && dy != 0 {
+                    pixel_size * std::f64::consts::SQRT_2
+                } else {
+                    pixel_size
+                };
+                // Half of the step is spent in each of the two cells
+                let cost = current_cost
+                    + step / 2.0 / current_terrain.speed
+                    + step / 2.0 / next_terrain.speed;
+                if cost < costs[index(next)] {
+                    costs[index(next)] = cost;
+                    previous[index(next)] = Some(current);
+                    let priority = ((cost + heuristic(next)) * 100.0) as u64;
+                    open.push(Reverse((priority, next)));
+                }
+            }
+        }
+        if costs[index(goal)].is_infinite() {
+            return None;
+        }
+        let mut path = vec![goal];
+        let mut current = goal;
+        while let Some(prev) = previous[index(current)] {
+            path.push(prev);
+            current = prev;
+        }
+        path.reverse();
+        let mut distance = 0.0;
+        let mut time = 0.0;
+        let mut exposure = 0.0;
+        for (a, b) in path.iter().zip(path.iter().skip(1)) {
+            let step = if a.0 != b.0 && a.1 != b.1 {
+                pixel_size * std::f64::consts::SQRT_2
+            } else {
+                pixel_size
+            };
+            let terrain = self.grid[index(*b)];
+            distance += step;
+            time += step / terrain.speed;
+            exposure += step / terrain.speed * terrain.exposure;
+        }
+        let travel_distance = costs[index(goal)];
+        let mut waypoints = vec![Waypoint {
+            x: from.0,
+            y: from.1,
+        }];
+        for (i, point) in path.iter().enumerate().skip(1) {
+            if i + 1 >= path.len() {
+                break;
+            }
+            let prev = path[i - 1];
+            let next = path[i + 1];
+            if (point.0 - prev.0, point.1 - prev.1) != (next.0 - point.0, next.1 - point.1) {
+                let (x, y) = self.to_world(*point);
+                waypoints.push(Waypoint { x, y });
+            }
+        }
+        waypoints.push(Waypoint { x: to.0, y: to.1 });
+        Some(Route {
+            waypoints,
+            distance: distance as i32,
+            travel_distance: travel_distance as i32,
+            exposure: if time > 0.0 {
+                exposure / time
+            } else {
+                self.get_terrain(from.0, from.1).exposure
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_find_route_to_self() {
+        let route = DEFAULT_MAP.find_route((1300, 2000), (1300, 2000)).unwrap();
+        assert_eq!(0, route.distance);
+        assert_eq!(0, route.travel_distance);
+        assert_eq!(2, route.waypoints.len());
+    }
+
+    #[test]
+    fn can_find_route_across_map() {
+        let from = (200, 2400);
+        let to = (2400, 200);
+        let route = DEFAULT_MAP.find_route(from, to).unwrap();
+        assert!(route.distance >= get_distance(from, to) * 9 / 10);
+        assert!(route.travel_distance >= route.distance);
+        assert!(route.exposure > 0.0);
+        assert_eq!(from, (route.waypoints[0].x, route.waypoints[0].y));
+    }
+}
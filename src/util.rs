@@ -3,17 +3,28 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use rand::Rng;
+
 pub fn roll_dice(chance: f64, rolls: i32) -> bool {
+    roll_dice_with(&mut rand::thread_rng(), chance, rolls)
+}
+
+pub fn skill_roll(chance: f64, skill_level: i32) -> bool {
+    roll_dice(chance, skill_level + 1)
+}
+
+/// Like `roll_dice`, but draws from the given random number generator.
+pub fn roll_dice_with<R: Rng>(rng: &mut R, chance: f64, rolls: i32) -> bool {
     if rolls < 1 {
         return false;
     }
     let die_sides = 1.0 / chance;
     let probability = 1.0 - ((die_sides - 1.0) / die_sides).powi(rolls);
-    rand::random::<f64>() < probability
+    rng.gen::<f64>() < probability
 }
 
-pub fn skill_roll(chance: f64, skill_level: i32) -> bool {
-    roll_dice(chance, skill_level + 1)
+pub fn skill_roll_with<R: Rng>(rng: &mut R, chance: f64, skill_level: i32) -> bool {
+    roll_dice_with(rng, chance, skill_level + 1)
 }
 
 pub fn get_sector_name(sector: (i32, i32)) -> String {
@@ -0,0 +1,215 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use rand::{seq::SliceRandom, Rng};
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        bunkers, messages,
+        worlds::{self, Weather, WorldTime},
+    },
+    error,
+};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeatherReport {
+    pub season: Season,
+    pub weather: Weather,
+    pub forecast: Weather,
+    pub next_change: Option<DateTime<Utc>>,
+}
+
+pub fn get_season(date: NaiveDateTime) -> Season {
+    match date.month() {
+        3..=5 => Season::Spring,
+        6..=8 => Season::Summer,
+        9..=11 => Season::Autumn,
+        _ => Season::Winter,
+    }
+}
+
+impl Weather {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weather::Clear => "Clear skies",
+            Weather::Storm => "Storm",
+            Weather::AcidRain => "Acid rain",
+            Weather::HeatWave => "Heat wave",
+            Weather::Blizzard => "Blizzard",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Weather::Clear => "Conditions on the surface are calm.",
+            Weather::Storm => {
+                "A storm is raging on the surface. Expeditions will be slower, \
+                but marauders are less likely to be out."
+            }
+            Weather::AcidRain => {
+                "Acid rain is falling on the surface. Expeditions will be exposed to \
+                much more contamination."
+            }
+            Weather::HeatWave => {
+                "A heat wave has hit the surface. Expeditions will be slower and the air \
+                recycling system is under increased load."
+            }
+            Weather::Blizzard => {
+                "A blizzard is sweeping across the surface. Travel will be very slow and \
+                the air recycling system is under increased load."
+            }
+        }
+    }
+
+    pub fn travel_time_multiplier(&self) -> f64 {
+        match self {
+            Weather::Clear => 1.0,
+            Weather::Storm => 1.3,
+            Weather::AcidRain => 1.1,
+            Weather::HeatWave => 1.15,
+            Weather::Blizzard => 1.6,
+        }
+    }
+
+    pub fn encounter_multiplier(&self) -> f64 {
+        match self {
+            Weather::Clear => 1.0,
+            Weather::Storm => 0.5,
+            Weather::AcidRain => 0.7,
+            Weather::HeatWave => 0.8,
+            Weather::Blizzard => 0.4,
+        }
+    }
+
+    pub fn exposure_multiplier(&self) -> f64 {
+        match self {
+            Weather::Clear => 1.0,
+            Weather::Storm => 1.3,
+            Weather::AcidRain => 2.0,
+            Weather::HeatWave => 1.1,
+            Weather::Blizzard => 1.2,
+        }
+    }
+
+    /// How much faster the air recycling system wears down.
+    pub fn air_load(&self) -> i32 {
+        match self {
+            Weather::HeatWave | Weather::Blizzard | Weather::Storm => 2,
+            _ => 1,
+        }
+    }
+}
+
+fn choose_weather<R: Rng>(rng: &mut R, season: Season) -> Weather {
+    let weights: &[(Weather, i32)] = match season {
+        Season::Spring => &[
+            (Weather::Clear, 6),
+            (Weather::Storm, 2),
+            (Weather::AcidRain, 3),
+        ],
+        Season::Summer => &[
+            (Weather::Clear, 6),
+            (Weather::Storm, 1),
+            (Weather::HeatWave, 3),
+        ],
+        Season::Autumn => &[
+            (Weather::Clear, 5),
+            (Weather::Storm, 4),
+            (Weather::AcidRain, 2),
+        ],
+        Season::Winter => &[
+            (Weather::Clear, 5),
+            (Weather::Storm, 1),
+            (Weather::Blizzard, 4),
+        ],
+    };
+    weights
+        .choose_weighted(rng, |(_, weight)| *weight)
+        .map(|(weather, _)| *weather)
+        .unwrap_or_default()
+}
+
+pub fn get_report(world: &WorldTime) -> WeatherReport {
+    WeatherReport {
+        season: get_season(world.now()),
+        weather: world.data.weather,
+        forecast: world.data.forecast,
+        next_change: world.data.next_weather_change,
+    }
+}
+
+pub async fn handle_tick(pool: &PgPool, world: &mut WorldTime) -> Result<(), error::Error> {
+    let now = Utc::now();
+    let mut changed = false;
+    match world.data.next_weather_change {
+        Some(next_change) if next_change > now => return Ok(()),
+        Some(_) => {
+            changed = world.data.weather != world.data.forecast;
+            world.data.weather = world.data.forecast;
+        }
+        None => (),
+    }
+    let mut rng = rand::thread_rng();
+    let hours = rng.gen_range(24..96);
+    let next_change = now + Duration::hours(hours) / world.time_acceleration;
+    world.data.forecast =
+        choose_weather(&mut rng, get_season(world.now() + Duration::hours(hours)));
+    world.data.next_weather_change = Some(next_change);
+    worlds::update_world_data(pool, world, &["weather", "forecast", "nextWeatherChange"]).await?;
+    if changed {
+        announce(pool, world).await?;
+    }
+    Ok(())
+}
+
+async fn announce(pool: &PgPool, world: &WorldTime) -> Result<(), error::Error> {
+    for bunker_id in bunkers::get_bunker_ids(pool, world.id).await? {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker_id,
+                sender_name: format!("Weather station"),
+                subject: format!("Weather report: {}", world.data.weather.name()),
+                body: format!(
+                    "{}\n\nForecast: {}",
+                    world.data.weather.description(),
+                    world.data.forecast.name()
+                ),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn blizzards_only_in_winter() {
+        let mut rng = rand::thread_rng();
+        for season in [Season::Spring, Season::Summer, Season::Autumn] {
+            for _ in 0..100 {
+                assert_ne!(Weather::Blizzard, choose_weather(&mut rng, season));
+            }
+        }
+        let january = NaiveDate::from_ymd(2070, 1, 15).and_hms(12, 0, 0);
+        assert_eq!(Season::Winter, get_season(january));
+    }
+}
@@ -12,7 +12,7 @@ use crate::{
         inhabitants::{Assignment, Inhabitant, SkillType},
         items, messages,
     },
-    error,
+    error, storage,
     util::skill_roll,
 };
 
@@ -46,6 +46,7 @@ pub async fn handle_tick(
             i.is_ready() && !i.data.sleeping && i.data.assignment == Some(Assignment::Workshop)
         })
         .collect();
+    let mut free_space = storage::get_free_space(pool, bunker).await?;
     let projects = &mut bunker.data.workshop.projects;
     let mut common_xp = 0;
     for worker in &mut workers {
@@ -61,15 +62,20 @@ pub async fn handle_tick(
                 .recipe
                 .as_ref()
                 .ok_or_else(|| error::internal_error("Invalid crafting recipe"))?;
-            if recipe.min_level > crafting_level {
+            if recipe.min_level > crafting_level || item_type.volume > free_space {
                 continue;
             }
             if skill_roll(0.5, crafting_level - recipe.min_level) {
                 project.progress += 1;
                 worker.add_xp(SkillType::Crafting, 30);
+                if recipe.radiation > 0 && !skill_roll(0.2, crafting_level - recipe.min_level) {
+                    worker.data.surface_exposure += recipe.radiation;
+                    worker.changed = true;
+                }
                 common_xp += 10;
                 let produced = project.progress / (project.max / project.quantity);
                 if produced > project.produced {
+                    free_space -= item_type.volume * (produced - project.produced) as f64;
                     items::add_item(pool, bunker.id, &item_type.id, produced - project.produced)
                         .await?;
                     project.produced = produced;
@@ -0,0 +1,339 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use actix::Addr;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    broadcaster::{Broadcaster, Message, WorldMessage},
+    data::{
+        WorldEventCondition, WorldEventEffect, WorldEventType, LOCATION_TYPES, WORLD_EVENT_TYPES,
+    },
+    db::{
+        bunkers, inhabitants, locations, messages, sectors,
+        worlds::{self, ActiveWorldEvent, WorldTime},
+    },
+    error,
+    generate::{generate_location_position, generate_position},
+    location, terrain,
+    util::{get_sector, get_sector_name, roll_dice},
+    weather,
+};
+
+pub async fn handle_tick(
+    pool: &PgPool,
+    world: &mut WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    let now = Utc::now();
+    match world.data.next_event_check {
+        Some(next_check) if next_check > now => return Ok(()),
+        Some(_) => {
+            end_events(pool, world).await?;
+            start_events(pool, world, broadcaster).await?;
+        }
+        None => (),
+    }
+    world.data.next_event_check = Some(now + Duration::hours(24) / world.time_acceleration);
+    worlds::update_world_data(pool, world, &["events", "nextEventCheck"]).await?;
+    Ok(())
+}
+
+/// Removes the events that have ended from the active events and returns them.
+fn take_ended_events(world: &mut WorldTime, now: DateTime<Utc>) -> Vec<ActiveWorldEvent> {
+    let (ended, active) = std::mem::take(&mut world.data.events)
+        .into_iter()
+        .partition(|e| e.ends <= now);
+    world.data.events = active;
+    ended
+}
+
+fn is_active(world: &WorldTime, event_type: &WorldEventType) -> bool {
+    world
+        .data
+        .events
+        .iter()
+        .any(|e| e.event_type == event_type.id)
+}
+
+fn get_end_time(
+    world: &WorldTime,
+    event_type: &WorldEventType,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    now + Duration::minutes((event_type.duration_days * 24.0 * 60.0) as i64)
+        / world.time_acceleration
+}
+
+async fn end_events(pool: &PgPool, world: &mut WorldTime) -> Result<(), error::Error> {
+    for event in take_ended_events(world, Utc::now()) {
+        for location_id in &event.location_ids {
+            let mut location = locations::get_location(pool, *location_id).await?;
+            location.data.depleted = true;
+            locations::update_location(pool, &location).await?;
+        }
+        let event_type = match WORLD_EVENT_TYPES.get(&event.event_type) {
+            Some(event_type) => event_type,
+            None => continue,
+        };
+        if let Some(end_text) = &event_type.end_text {
+            let sector_name = get_sector_name((event.sector_x, event.sector_y));
+            notify_bunkers(
+                pool,
+                world.id,
+                &format!("{} (Sector {})", event_type.name, sector_name),
+                &end_text.replace("{sector}", &sector_name),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn start_events(
+    pool: &PgPool,
+    world: &mut WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+) -> Result<(), error::Error> {
+    let bunker_count = bunkers::get_bunker_ids(pool, world.id).await?.len() as i32;
+    let mut event_types: Vec<&WorldEventType> = WORLD_EVENT_TYPES.values().collect();
+    event_types.sort_by_key(|t| &t.id);
+    for event_type in event_types {
+        if is_active(world, event_type)
+            || !conditions_met(world, event_type, bunker_count)
+            || !roll_dice(event_type.chance, 1)
+        {
+            continue;
+        }
+        start_event(pool, world, broadcaster, event_type).await?;
+    }
+    Ok(())
+}
+
+fn conditions_met(world: &WorldTime, event_type: &WorldEventType, bunker_count: i32) -> bool {
+    event_type
+        .conditions
+        .iter()
+        .all(|condition| match condition {
+            WorldEventCondition::Season { season } => weather::get_season(world.now()) == *season,
+            WorldEventCondition::Weather { weather } => world.data.weather == *weather,
+            // Game days, counted in minutes so that accelerated worlds don't skip ahead in steps
+            WorldEventCondition::MinDays { days } => {
+                (Utc::now() - world.created).num_minutes() * world.time_acceleration as i64
+                    / (24 * 60)
+                    >= *days
+            }
+            WorldEventCondition::MinBunkers { count } => bunker_count >= *count,
+        })
+}
+
+/// Starts an event regardless of its conditions and adds it to the active events of the world.
+/// The caller is responsible for saving the list of events.
+pub async fn start_event(
+    pool: &PgPool,
+    world: &mut WorldTime,
+    broadcaster: &Addr<Broadcaster>,
+    event_type: &WorldEventType,
+) -> Result<(), error::Error> {
+    if is_active(world, event_type) {
+        Err(error::client_error("EVENT_ALREADY_ACTIVE"))?;
+    }
+    let map = terrain::get_world_map(pool, world.id).await?;
+    let (x, y) = generate_position(&map);
+    let sector = get_sector(x, y);
+    let sector_name = get_sector_name(sector);
+    let mut location_ids = vec![];
+    for effect in &event_type.effects {
+        match effect {
+            WorldEventEffect::SpawnLocation {
+                location_type,
+                reveal,
+            } => {
+                let location_type = LOCATION_TYPES
+                    .get(location_type)
+                    .ok_or_else(|| error::internal_error("Location type not found"))?;
+                let (x, y) = (0..100)
+                    .map(|_| generate_location_position(&map, location_type.placement))
+                    .find(|&(x, y)| get_sector(x, y) == sector)
+                    .unwrap_or((x, y));
+                let name = location::get_new_location_name(pool, world.id, location_type).await?;
+                let location_id = locations::create_location(
+                    pool,
+                    &locations::NewLocation {
+                        world_id: world.id,
+                        name,
+                        x,
+                        y,
+                        data: locations::LocationData {
+                            location_type: location_type.id.clone(),
+                            searches: 0,
+                            bunker_id: None,
+                            depleted: false,
+                        },
+                    },
+                )
+                .await?;
+                if *reveal {
+                    for bunker_id in bunkers::get_bunker_ids(pool, world.id).await? {
+                        locations::add_bunker_location(pool, bunker_id, location_id).await?;
+                    }
+                }
+                location_ids.push(location_id);
+            }
+            WorldEventEffect::Hazard { amount } => {
+                let hazard = sectors::get_sector_hazard(pool, world.id, sector.0, sector.1).await?;
+                sectors::set_sector_hazard(
+                    pool,
+                    world.id,
+                    sector.0,
+                    sector.1,
+                    (hazard + amount).min(100),
+                )
+                .await?;
+            }
+            WorldEventEffect::Sickness { chance, radius } => {
+                for bunker in bunkers::get_bunkers(pool, world.id).await? {
+                    let bunker_sector = get_sector(bunker.x, bunker.y);
+                    if (bunker_sector.0 - sector.0).abs() > *radius
+                        || (bunker_sector.1 - sector.1).abs() > *radius
+                    {
+                        continue;
+                    }
+                    for mut inhabitant in inhabitants::get_inhabitants(pool, bunker.id).await? {
+                        if !inhabitant.data.sick && roll_dice(*chance, 1) {
+                            inhabitant.data.sick = true;
+                            inhabitant.data.contagious = true;
+                            inhabitant.data.recovering = false;
+                            inhabitants::update_inhabitant_data(pool, &inhabitant).await?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let ends = get_end_time(world, event_type, Utc::now());
+    world.data.events.push(ActiveWorldEvent {
+        event_type: event_type.id.clone(),
+        sector_x: sector.0,
+        sector_y: sector.1,
+        ends,
+        location_ids,
+    });
+    let text = event_type.text.replace("{sector}", &sector_name);
+    notify_bunkers(
+        pool,
+        world.id,
+        &format!("{} (Sector {})", event_type.name, sector_name),
+        &text,
+    )
+    .await?;
+    broadcaster.do_send(WorldMessage {
+        world_id: world.id,
+        message: Message::WorldEvent {
+            event_type: event_type.id.clone(),
+            name: event_type.name.clone(),
+            text,
+        },
+    });
+    Ok(())
+}
+
+async fn notify_bunkers(
+    pool: &PgPool,
+    world_id: i32,
+    subject: &str,
+    body: &str,
+) -> Result<(), error::Error> {
+    for bunker_id in bunkers::get_bunker_ids(pool, world_id).await? {
+        messages::create_system_message(
+            pool,
+            &messages::NewSystemMessage {
+                receiver_bunker_id: bunker_id,
+                sender_name: format!("Radio operator"),
+                subject: subject.to_owned(),
+                body: body.to_owned(),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::Json;
+
+    use super::*;
+    use crate::db::worlds::{Weather, WorldData};
+
+    fn create_world(age_days: i64) -> WorldTime {
+        WorldTime {
+            id: 1,
+            created: Utc::now() - Duration::days(age_days) / 10,
+            start_year: 2070,
+            time_acceleration: 10,
+            time_offset: 0,
+            data: Json(WorldData {
+                weather: Weather::Storm,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn create_event_type(conditions: Vec<WorldEventCondition>) -> WorldEventType {
+        WorldEventType {
+            id: "test".to_owned(),
+            name: "Test".to_owned(),
+            text: "Something happened in sector {sector}".to_owned(),
+            end_text: None,
+            chance: 1.0,
+            duration_days: 2.0,
+            conditions,
+            effects: vec![],
+        }
+    }
+
+    #[test]
+    fn all_conditions_must_be_met() {
+        let event_type = create_event_type(vec![
+            WorldEventCondition::Weather {
+                weather: Weather::Storm,
+            },
+            WorldEventCondition::MinDays { days: 30 },
+            WorldEventCondition::MinBunkers { count: 2 },
+        ]);
+        assert!(conditions_met(&create_world(31), &event_type, 2));
+        assert!(!conditions_met(&create_world(29), &event_type, 2));
+        assert!(!conditions_met(&create_world(31), &event_type, 1));
+        let mut world = create_world(31);
+        world.data.weather = Weather::Clear;
+        assert!(!conditions_met(&world, &event_type, 2));
+        assert!(conditions_met(&world, &create_event_type(vec![]), 0));
+    }
+
+    #[test]
+    fn ended_events_are_removed() {
+        let mut world = create_world(0);
+        let event_type = create_event_type(vec![]);
+        let now = Utc::now();
+        let ends = get_end_time(&world, &event_type, now);
+        // Two days of game time pass in under five hours at ten times speed
+        assert_eq!(Duration::hours(2 * 24) / 10, ends - now);
+        world.data.events.push(ActiveWorldEvent {
+            event_type: event_type.id.clone(),
+            sector_x: 0,
+            sector_y: 0,
+            ends,
+            location_ids: vec![],
+        });
+        assert!(is_active(&world, &event_type));
+        assert!(take_ended_events(&mut world, now).is_empty());
+        assert!(is_active(&world, &event_type));
+        let ended = take_ended_events(&mut world, ends);
+        assert_eq!(1, ended.len());
+        assert!(!is_active(&world, &event_type));
+    }
+}
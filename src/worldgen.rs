@@ -0,0 +1,260 @@
+/* Copyright (c) 2022 Niels Sonnich Poulsen (http://nielssp.dk)
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    data::{TerrainType, TERRAIN_TYPES},
+    db::worlds::MapParams,
+    error,
+};
+
+struct City {
+    x: i32,
+    y: i32,
+    radius: i32,
+}
+
+/// Generates a world map where each pixel has the first color of its terrain type.
+pub fn generate_map(params: &MapParams) -> Result<DynamicImage, error::Error> {
+    if params.size < 64 || params.size > 2048 {
+        Err(error::client_error("INVALID_MAP_SIZE"))?;
+    }
+    if !(0.0..0.9).contains(&params.water_level) {
+        Err(error::client_error("INVALID_WATER_LEVEL"))?;
+    }
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let size = params.size as i32;
+    let water = find_terrain(|t| t.water)?;
+    let open_ground = find_terrain(|t| !t.water && !t.urban && !t.impassable && t.hazard == 0)?;
+    let rubble = get_terrain("rubble")?;
+    let road = get_terrain("road")?;
+    let hotspot = find_terrain(|t| !t.impassable && t.hazard > 0)?;
+
+    let heights = generate_heightmap(&mut rng, size);
+    let mut sorted = heights.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let sea_level =
+        sorted[((sorted.len() as f64 * params.water_level) as usize).min(sorted.len() - 1)];
+    let is_land = |x: i32, y: i32| heights[(y * size + x) as usize] > sea_level;
+
+    let mut image = RgbImage::new(params.size, params.size);
+    for y in 0..size {
+        for x in 0..size {
+            let terrain = if is_land(x, y) { open_ground } else { water };
+            image.put_pixel(x as u32, y as u32, color(terrain));
+        }
+    }
+
+    let cities = place_cities(&mut rng, size, params.cities, &is_land);
+    for city in &cities {
+        for y in (city.y - city.radius).max(0)..(city.y + city.radius).min(size) {
+            for x in (city.x - city.radius).max(0)..(city.x + city.radius).min(size) {
+                let dx = x - city.x;
+                let dy = y - city.y;
+                if dx * dx + dy * dy > city.radius * city.radius || !is_land(x, y) {
+                    continue;
+                }
+                let terrain = if dx % 8 == 0 || dy % 8 == 0 {
+                    road
+                } else {
+                    rubble
+                };
+                image.put_pixel(x as u32, y as u32, color(terrain));
+            }
+        }
+    }
+
+    // Connect the cities with a minimum spanning tree of roads
+    let mut connected = vec![false; cities.len()];
+    if !cities.is_empty() {
+        connected[0] = true;
+    }
+    for _ in 1..cities.len() {
+        let closest = (0..cities.len())
+            .filter(|&a| connected[a])
+            .flat_map(|a| {
+                (0..cities.len())
+                    .filter(|&b| !connected[b])
+                    .map(move |b| (a, b))
+            })
+            .min_by_key(|&(a, b)| {
+                (cities[a].x - cities[b].x).pow(2) + (cities[a].y - cities[b].y).pow(2)
+            });
+        if let Some((a, b)) = closest {
+            connected[b] = true;
+            draw_line(
+                &mut image,
+                (cities[a].x, cities[a].y),
+                (cities[b].x, cities[b].y),
+                road,
+            );
+        }
+    }
+
+    for _ in 0..params.hotspots {
+        let (center_x, center_y) = (rng.gen_range(0..size), rng.gen_range(0..size));
+        let radius = rng.gen_range(3..=size / 50 + 4);
+        for y in (center_y - radius).max(0)..(center_y + radius).min(size) {
+            for x in (center_x - radius).max(0)..(center_x + radius).min(size) {
+                let (dx, dy) = (x - center_x, y - center_y);
+                if dx * dx + dy * dy <= radius * radius && is_land(x, y) {
+                    image.put_pixel(x as u32, y as u32, color(hotspot));
+                }
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, error::Error> {
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+        .map_err(|_| error::internal_error("Failed encoding map"))?;
+    Ok(bytes)
+}
+
+fn get_terrain(id: &str) -> Result<&'static TerrainType, error::Error> {
+    TERRAIN_TYPES
+        .get(id)
+        .ok_or_else(|| error::internal_error("Terrain type not found"))
+}
+
+fn find_terrain<F>(predicate: F) -> Result<&'static TerrainType, error::Error>
+where
+    F: Fn(&TerrainType) -> bool,
+{
+    let mut terrain_types: Vec<&TerrainType> = TERRAIN_TYPES.values().collect();
+    terrain_types.sort_by_key(|t| &t.id);
+    terrain_types
+        .into_iter()
+        .find(|t| predicate(t))
+        .ok_or_else(|| error::internal_error("Terrain type not found"))
+}
+
+fn color(terrain: &TerrainType) -> Rgb<u8> {
+    Rgb(terrain.colors[0])
+}
+
+/// Layered value noise tilted towards a random direction so that the coastline ends up on one
+/// side of the map.
+fn generate_heightmap(rng: &mut StdRng, size: i32) -> Vec<f64> {
+    let mut heights = vec![0.0; (size * size) as usize];
+    let mut amplitude = 1.0;
+    let mut total_amplitude = 0.0;
+    let mut cell = size / 4;
+    while cell >= 4 {
+        let lattice = size / cell + 2;
+        let values: Vec<f64> = (0..lattice * lattice).map(|_| rng.gen()).collect();
+        for y in 0..size {
+            for x in 0..size {
+                let fx = x as f64 / cell as f64;
+                let fy = y as f64 / cell as f64;
+                let (x0, y0) = (fx.floor() as i32, fy.floor() as i32);
+                let tx = smooth(fx - x0 as f64);
+                let ty = smooth(fy - y0 as f64);
+                let value = |i: i32, j: i32| values[(j * lattice + i) as usize];
+                let top = lerp(value(x0, y0), value(x0 + 1, y0), tx);
+                let bottom = lerp(value(x0, y0 + 1), value(x0 + 1, y0 + 1), tx);
+                heights[(y * size + x) as usize] += lerp(top, bottom, ty) * amplitude;
+            }
+        }
+        total_amplitude += amplitude;
+        amplitude /= 2.0;
+        cell /= 2;
+    }
+    let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+    let (dx, dy) = (angle.cos(), angle.sin());
+    for y in 0..size {
+        for x in 0..size {
+            let gradient =
+                (x as f64 / size as f64 - 0.5) * dx + (y as f64 / size as f64 - 0.5) * dy;
+            let height = &mut heights[(y * size + x) as usize];
+            *height = *height / total_amplitude * 0.6 + (gradient + 0.5) * 0.4;
+        }
+    }
+    heights
+}
+
+fn place_cities<F>(rng: &mut StdRng, size: i32, count: i32, is_land: F) -> Vec<City>
+where
+    F: Fn(i32, i32) -> bool,
+{
+    let mut cities: Vec<City> = vec![];
+    let min_distance = size / 8;
+    for _ in 0..count * 50 {
+        if cities.len() as i32 >= count {
+            break;
+        }
+        let (x, y) = (rng.gen_range(0..size), rng.gen_range(0..size));
+        if !is_land(x, y)
+            || cities
+                .iter()
+                .any(|c| (c.x - x).pow(2) + (c.y - y).pow(2) < min_distance * min_distance)
+        {
+            continue;
+        }
+        let radius = rng.gen_range(size / 40..=size / 16);
+        cities.push(City { x, y, radius });
+    }
+    cities
+}
+
+fn draw_line(image: &mut RgbImage, from: (i32, i32), to: (i32, i32), terrain: &TerrainType) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).max(1);
+    for i in 0..=steps {
+        let x = from.0 + (to.0 - from.0) * i / steps;
+        let y = from.1 + (to.1 - from.1) * i / steps;
+        image.put_pixel(x as u32, y as u32, color(terrain));
+    }
+}
+
+fn smooth(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{TerrainMap, WORLD_SIZE};
+
+    fn params(seed: u64) -> MapParams {
+        MapParams {
+            seed,
+            size: 128,
+            water_level: 0.25,
+            cities: 4,
+            hotspots: 2,
+        }
+    }
+
+    #[test]
+    fn same_seed_generates_same_map() {
+        let a = generate_map(&params(42)).unwrap();
+        let b = generate_map(&params(42)).unwrap();
+        assert_eq!(a.as_bytes(), b.as_bytes());
+    }
+
+    #[test]
+    fn generated_map_has_water_and_cities() {
+        let map = TerrainMap::from_image(&generate_map(&params(7)).unwrap());
+        let step = WORLD_SIZE / 128;
+        let terrain: Vec<_> = (0..128)
+            .flat_map(|y| (0..128).map(move |x| (x * step, y * step)))
+            .map(|(x, y)| map.get_terrain(x, y))
+            .collect();
+        let water = terrain.iter().filter(|t| t.water).count() as f64 / terrain.len() as f64;
+        assert!(water > 0.15 && water < 0.35);
+        assert!(terrain.iter().any(|t| t.urban));
+    }
+}